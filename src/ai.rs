@@ -0,0 +1,345 @@
+use crate::pieces::Shape;
+use crate::{search, Action, Board, Player, Square};
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+use std::fs;
+
+//How much each feature of the stack is worth, positive is good
+#[derive(Copy, Clone)]
+pub struct Weights {
+    pub height: f64,
+    pub lines: f64,
+    pub holes: f64,
+    pub bumpiness: f64,
+    pub wells: f64,
+}
+
+impl Weights {
+    pub fn standard() -> Weights {
+        Weights {
+            height: -0.51,
+            lines: 0.76,
+            holes: -0.36,
+            bumpiness: -0.18,
+            wells: -0.05,
+        }
+    }
+
+    pub fn from_array(array: [f64; 5]) -> Weights {
+        Weights {
+            height: array[0],
+            lines: array[1],
+            holes: array[2],
+            bumpiness: array[3],
+            wells: array[4],
+        }
+    }
+
+    pub fn to_array(self) -> [f64; 5] {
+        [self.height, self.lines, self.holes, self.bumpiness, self.wells]
+    }
+
+    pub fn to_text(self) -> String {
+        format!(
+            "height={} lines={} holes={} bumpiness={} wells={}",
+            self.height, self.lines, self.holes, self.bumpiness, self.wells
+        )
+    }
+
+    pub fn load(from: &str) -> Option<Weights> {
+        //A file saved by the tuner, or the weights written out right there
+        match fs::read_to_string(from) {
+            Ok(text) => {
+                let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with("//")).collect();
+                Weights::parse(&lines.join(" "))
+            }
+            Err(_) => Weights::parse(from),
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Weights> {
+        //name=value pairs split by commas or whitespace, anything left out stays standard
+        let mut weights = Weights::standard();
+        for pair in text.split(|c: char| c == ',' || c.is_whitespace()) {
+            if pair.is_empty() {
+                continue;
+            }
+            let (name, value) = pair.split_once('=')?;
+            let value = value.parse().ok()?;
+            match name {
+                "height" => weights.height = value,
+                "lines" => weights.lines = value,
+                "holes" => weights.holes = value,
+                "bumpiness" => weights.bumpiness = value,
+                "wells" => weights.wells = value,
+                _ => return None,
+            }
+        }
+        Some(weights)
+    }
+}
+
+pub fn evaluate(board: &Board, placement: &Player, weights: &Weights) -> f64 {
+    let mut field = board.field.clone();
+    let rows = board.pieces.rows(placement.shape, placement.orientation);
+    field.place(placement.y, placement.x, rows, Square::GARBAGE);
+
+    //Anything left in the buffer is game over
+    if field.any_filled(0..board.rules.buffer) {
+        return f64::NEG_INFINITY;
+    }
+    let lines = field.clear_lines();
+
+    let width = field.width();
+    let mut heights = vec![0; width];
+    let mut holes = 0;
+    for (x, height) in heights.iter_mut().enumerate() {
+        let column = (0..field.rows()).map(|y| field.row(y) & (1 << x) != 0);
+        let mut top = None;
+        for (y, filled) in column.enumerate() {
+            match (top, filled) {
+                (None, true) => top = Some(y),
+                (Some(_), false) => holes += 1,
+                _ => {}
+            }
+        }
+        *height = top.map_or(0, |top| field.rows() - top);
+    }
+
+    let bumpiness: usize = heights.windows(2).map(|h| h[0].abs_diff(h[1])).sum();
+
+    //Walls count as infinitely high when looking for wells
+    let mut wells = 0;
+    for x in 0..width {
+        let left = if x == 0 { usize::MAX } else { heights[x - 1] };
+        let right = if x == width - 1 { usize::MAX } else { heights[x + 1] };
+        let lowest = std::cmp::min(left, right);
+        if lowest != usize::MAX && lowest > heights[x] {
+            wells += lowest - heights[x];
+        }
+    }
+
+    weights.height * heights.iter().sum::<usize>() as f64
+        + weights.lines * lines as f64
+        + weights.holes * holes as f64
+        + weights.bumpiness * bumpiness as f64
+        + weights.wells * wells as f64
+}
+
+pub fn best(board: &Board, player: &Player, weights: &Weights) -> Option<(f64, Player)> {
+    best_ahead(board, player, weights, 1)
+}
+
+pub fn best_ahead(
+    board: &Board,
+    player: &Player,
+    weights: &Weights,
+    depth: usize,
+) -> Option<(f64, Player)> {
+    //With a depth of more than 1 every spot is scored by how well the next pieces fit after it
+    let next = board.grab_bag.last().copied().filter(|_| depth > 1);
+
+    search::placements(board, player)
+        .into_iter()
+        .map(|placement| {
+            let score = match next {
+                Some(shape) => ahead(board, &placement.player, shape, weights, depth),
+                None => evaluate(board, &placement.player, weights),
+            };
+            (score, placement.player)
+        })
+        .fold(None, |best, (score, player)| match best {
+            Some((top, _)) if top >= score => best,
+            _ => Some((score, player)),
+        })
+}
+
+fn ahead(board: &Board, placement: &Player, next: Shape, weights: &Weights, depth: usize) -> f64 {
+    let mut after = board.clone();
+    let rows = board.pieces.rows(placement.shape, placement.orientation);
+    after.field.place(placement.y, placement.x, rows, Square::GARBAGE);
+    if after.topped_out() {
+        return f64::NEG_INFINITY;
+    }
+    let lines = after.field.clear_lines();
+
+    after.grab_bag.pop();
+    after.player = after.spawn(next);
+    if after.collision(&after.player) {
+        return f64::NEG_INFINITY;
+    }
+
+    //The lines this piece clears are gone by the time the next one is scored
+    weights.lines * lines as f64
+        + best_ahead(&after, &after.player, weights, depth - 1).map_or(f64::NEG_INFINITY, |b| b.0)
+}
+
+//What the bot has decided to do with the piece it has
+enum Plan {
+    Hold,
+    Place(Player),
+}
+
+pub struct Bot {
+    pub weights: Weights,
+    pub delay: usize,
+    //Pieces per second at most, how many pieces it looks ahead and how often it plays
+    //somewhere at random instead
+    pub pps: Option<f64>,
+    pub depth: usize,
+    pub mistakes: f64,
+    wait: usize,
+    plan: Option<Plan>,
+    planned_for: Option<usize>,
+    dropped: bool,
+    piece_ticks: usize,
+    piece_for: Option<usize>,
+}
+
+impl Bot {
+    pub fn new(weights: Weights) -> Bot {
+        Bot {
+            weights,
+            delay: 5,
+            pps: None,
+            depth: 1,
+            mistakes: 0.0,
+            wait: 0,
+            plan: None,
+            planned_for: None,
+            dropped: false,
+            piece_ticks: 0,
+            piece_for: None,
+        }
+    }
+
+    pub fn difficulty(weights: Weights, name: &str) -> Option<Bot> {
+        let mut bot = Bot::new(weights);
+        match name {
+            "easy" => {
+                bot.pps = Some(0.5);
+                bot.mistakes = 0.15;
+            }
+            "medium" => {
+                bot.pps = Some(1.0);
+                bot.mistakes = 0.05;
+            }
+            "hard" => {
+                bot.pps = Some(2.0);
+                bot.depth = 2;
+            }
+            "max" => {
+                bot.delay = 0;
+                bot.depth = 2;
+            }
+            _ => return None,
+        }
+        Some(bot)
+    }
+
+    fn think(&self, board: &Board, can_hold: bool) -> Option<Plan> {
+        if self.mistakes > 0.0 && thread_rng().gen_bool(self.mistakes) {
+            return search::placements(board, &board.player)
+                .choose(&mut thread_rng())
+                .map(|placement| Plan::Place(placement.player));
+        }
+
+        //The hold piece, or the next one if nothing is held yet, is tried from the top
+        let current = best_ahead(board, &board.player, &self.weights, self.depth);
+
+        let other = board
+            .held
+            .or_else(|| board.grab_bag.last().copied())
+            .filter(|_| can_hold && board.rules.hold);
+        if let (Some((score, _)), Some(shape)) = (&current, other) {
+            let start = board.spawn(shape);
+            if let Some((held, _)) = best_ahead(board, &start, &self.weights, self.depth) {
+                if held > *score {
+                    return Some(Plan::Hold);
+                }
+            }
+        }
+
+        current.map(|(_, target)| Plan::Place(target))
+    }
+
+    pub fn action(&mut self, board: &Board, can_hold: bool) -> Option<Action> {
+        //One action every few ticks so people can watch, but soft drops go as fast as they can
+        if self.piece_for != Some(board.locked) {
+            self.piece_for = Some(board.locked);
+            self.piece_ticks = 0;
+        }
+        self.piece_ticks += 1;
+
+        if self.wait > 0 {
+            self.wait -= 1;
+            return None;
+        }
+
+        let action = self.next_action(board, can_hold);
+        if action != Some(Action::SoftDrop) {
+            self.wait = self.delay;
+        }
+        action
+    }
+
+    fn too_soon(&self) -> bool {
+        //Ticks are 10ms, so a piece a second is 100 of them
+        self.pps.is_some_and(|pps| (self.piece_ticks as f64) < 100.0 / pps)
+    }
+
+    fn next_action(&mut self, board: &Board, can_hold: bool) -> Option<Action> {
+        if self.planned_for != Some(board.locked) {
+            self.plan = self.think(board, can_hold);
+            self.planned_for = Some(board.locked);
+            self.dropped = false;
+        }
+
+        let target = match &self.plan {
+            Some(Plan::Place(target)) => *target,
+            Some(Plan::Hold) => {
+                //The plan was for the other piece, so start over with it
+                self.planned_for = None;
+                return Some(Action::Hold);
+            }
+            None => return None,
+        };
+
+        //The way there is found again every time since gravity keeps moving the piece
+        match search::path_to(board, &board.player, &board.cells(&target)) {
+            //Sits over its spot until it's been long enough for the speed it's capped at
+            Some(path) if path.iter().all(|a| *a == Action::SoftDrop) && self.too_soon() => None,
+            Some(path) => follow(board, &path, &mut self.dropped),
+            None => {
+                self.planned_for = None;
+                None
+            }
+        }
+    }
+}
+
+pub fn follow(board: &Board, path: &[Action], dropped: &mut bool) -> Option<Action> {
+    //The next input along a path from search, finishing up with a drop
+    let mut drop = || {
+        //Once is enough, another one would hold off the lock
+        if *dropped {
+            None
+        } else {
+            *dropped = true;
+            Some(Action::HardDrop)
+        }
+    };
+
+    match path.first() {
+        Some(Action::SoftDrop) if board.rules.hard_drop => {
+            if path.iter().all(|action| *action == Action::SoftDrop) {
+                drop()
+            } else {
+                Some(Action::SoftDrop)
+            }
+        }
+        Some(action) => Some(*action),
+        None if board.rules.hard_drop => drop(),
+        None => Some(Action::SoftDrop),
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{Board, Player};
+use ncurses::*;
+use std::collections::VecDeque;
+
+//Rows sent for clearing 0 to 4 lines, without and with a T-spin
+const LINES: [usize; 5] = [0, 0, 1, 2, 4];
+const T_SPIN: [usize; 4] = [0, 2, 4, 6];
+const T_SPIN_MINI: [usize; 3] = [0, 0, 1];
+
+//Extra rows for clearing with several pieces in a row, by how long the streak is
+const COMBO: [usize; 12] = [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+
+const BACK_TO_BACK: usize = 1;
+const PERFECT_CLEAR: usize = 10;
+
+//Garbage rows that come in together after a piece locks, the rest wait for the next one
+const MAX_INCOMING: usize = 8;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+pub fn spin(board: &Board, player: &Player, rotated: bool) -> Spin {
+    //A T that got where it is by rotating, with three of the corners around its middle taken.
+    //It's only a mini if one of the two corners it points at is open.
+    if !rotated || board.pieces.name(player.shape) != "T" || board.size(player) != 3 {
+        return Spin::None;
+    }
+
+    let taken = |y: isize, x: isize| {
+        !board.in_bounds(player.y + y, player.x + x)
+            || board.field.row((player.y + y) as usize) & (1 << (player.x + x)) != 0
+    };
+    //Corners clockwise from the top left, the first two are in front when pointing up
+    let corners = [taken(0, 0), taken(0, 2), taken(2, 2), taken(2, 0)];
+    let front = (0..2)
+        .filter(|i| corners[(player.orientation + i) % 4])
+        .count();
+
+    match corners.iter().filter(|c| **c).count() {
+        0..=2 => Spin::None,
+        _ if front == 2 => Spin::Full,
+        _ => Spin::Mini,
+    }
+}
+
+//What one side sends and what's waiting to come up under it
+#[derive(Clone, Hash)]
+pub struct Attack {
+    incoming: VecDeque<usize>,
+    outgoing: usize,
+    combo: Option<usize>,
+    back_to_back: bool,
+}
+
+impl Attack {
+    pub fn new() -> Attack {
+        Attack {
+            incoming: VecDeque::new(),
+            outgoing: 0,
+            combo: None,
+            back_to_back: false,
+        }
+    }
+
+    pub fn cleared(&mut self, lines: usize, spin: Spin, perfect: bool) {
+        //Works out the attack for a piece that just locked, and uses it to cancel incoming
+        //garbage before any is sent
+        if lines == 0 {
+            self.combo = None;
+            return;
+        }
+
+        let lines = std::cmp::min(lines, 4);
+        let mut rows = match spin {
+            Spin::None => LINES[lines],
+            Spin::Mini => T_SPIN_MINI[std::cmp::min(lines, 2)],
+            Spin::Full => T_SPIN[std::cmp::min(lines, 3)],
+        };
+
+        let combo = self.combo.map_or(0, |c| c + 1);
+        rows += COMBO[std::cmp::min(combo, COMBO.len() - 1)];
+        self.combo = Some(combo);
+
+        //Tetrises and T-spins one after another, as long as nothing easier was cleared between
+        let difficult = lines == 4 || spin != Spin::None;
+        if difficult && self.back_to_back {
+            rows += BACK_TO_BACK;
+        }
+        self.back_to_back = difficult;
+
+        if perfect {
+            rows += PERFECT_CLEAR;
+        }
+
+        while rows > 0 {
+            match self.incoming.front_mut() {
+                Some(front) if *front > rows => {
+                    *front -= rows;
+                    rows = 0;
+                }
+                Some(front) => {
+                    rows -= *front;
+                    self.incoming.pop_front();
+                }
+                None => break,
+            }
+        }
+        self.outgoing += rows;
+    }
+
+    pub fn receive(&mut self, rows: usize) {
+        if rows > 0 {
+            self.incoming.push_back(rows);
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.incoming.iter().sum()
+    }
+
+    pub fn take_outgoing(&mut self) -> usize {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    pub fn take_incoming(&mut self) -> Vec<usize> {
+        //Attacks that arrive now, oldest first, each of which gets its own hole
+        let mut batches = Vec::new();
+        let mut total = 0;
+        while let Some(rows) = self.incoming.pop_front() {
+            let rows_now = std::cmp::min(rows, MAX_INCOMING - total);
+            batches.push(rows_now);
+            total += rows_now;
+            if rows_now < rows {
+                self.incoming.push_front(rows - rows_now);
+            }
+            if total == MAX_INCOMING {
+                break;
+            }
+        }
+        batches
+    }
+
+    pub fn draw(&self, window: WINDOW) {
+        //A column of what's about to come up, from the bottom of the board
+        let height = getmaxy(window);
+        let pending = std::cmp::min(self.pending() as i32, height);
+
+        werase(window);
+        wattron(window, COLOR_PAIR(5) | A_BOLD());
+        for y in height - pending..height {
+            mvwaddch(window, y, 0, '#' as chtype);
+        }
+        wattroff(window, COLOR_PAIR(5) | A_BOLD());
+        wrefresh(window);
+    }
+}
@@ -0,0 +1,464 @@
+use rand::{thread_rng, Rng};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+//Matchmaking for network games. Players register with a name, open or join rooms of 2 to 8 and
+//ready up, then everyone in the room gets the same seed and rules. During a game the lobby
+//passes attacks, boards and who's out between them, and says who won.
+
+const PORT: u16 = 7779;
+const MIN_PLAYERS: usize = 2;
+const MAX_PLAYERS: usize = 8;
+const MAX_NAME: usize = 16;
+
+//Lines waiting to go out to one client, a few seconds of boards from a full room. One that falls
+//further behind than this is dropped rather than holding everyone else up.
+const QUEUE: usize = 512;
+
+//Flags that are up to each player, on their own and followed by a value. Rooms don't pass
+//them on, a file only its opener has would stop everyone else from playing.
+const PERSONAL: [&str; 4] = ["--ai", "--hint", "--finesse", "--finesse-training"];
+const PERSONAL_VALUES: [&str; 4] = ["--weights", "--tbp", "--broadcast", "--spectate"];
+
+fn rules(args: &Value) -> Value {
+    let mut rules = Vec::new();
+    let mut args = args.as_array().into_iter().flatten().filter_map(|a| a.as_str());
+    while let Some(arg) = args.next() {
+        if PERSONAL_VALUES.contains(&arg) {
+            args.next();
+        } else if !PERSONAL.contains(&arg) {
+            rules.push(json!(arg));
+        }
+    }
+    Value::Array(rules)
+}
+
+struct Client {
+    name: String,
+    //Written out by the client's own thread, so a slow one doesn't stall the lobby
+    outgoing: SyncSender<String>,
+    stream: TcpStream,
+    room: Option<usize>,
+}
+
+//A game in progress. Players are numbered by where they were in the room when it started.
+struct Match {
+    players: Vec<usize>,
+    alive: Vec<bool>,
+}
+
+struct Room {
+    name: String,
+    size: usize,
+    //The game flags of whoever opened it, everyone plays by them
+    args: Value,
+    players: Vec<usize>,
+    ready: Vec<bool>,
+    game: Option<Match>,
+}
+
+struct Lobby {
+    clients: BTreeMap<usize, Client>,
+    rooms: BTreeMap<usize, Room>,
+    next_client: usize,
+    next_room: usize,
+}
+
+impl Lobby {
+    fn new() -> Lobby {
+        Lobby {
+            clients: BTreeMap::new(),
+            rooms: BTreeMap::new(),
+            next_client: 0,
+            next_room: 0,
+        }
+    }
+
+    fn send(&self, client: usize, message: &Value) {
+        //A client that went away or can't keep up is hung up on, and cleaned up when its own
+        //thread notices
+        if let Some(client) = self.clients.get(&client) {
+            if client.outgoing.try_send(format!("{}\n", message)).is_err() {
+                let _ = client.stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    fn error(&self, client: usize, message: &str) {
+        self.send(client, &json!({"type": "error", "message": message}));
+    }
+
+    fn name(&self, client: usize) -> String {
+        self.clients.get(&client).map_or_else(String::new, |c| c.name.clone())
+    }
+
+    fn announce_rooms(&self) {
+        //Everyone who isn't in a room sees the list change
+        let rooms: Vec<Value> = self
+            .rooms
+            .iter()
+            .map(|(id, room)| {
+                json!({
+                    "id": id,
+                    "name": room.name,
+                    "players": room.players.len(),
+                    "size": room.size,
+                    "playing": room.game.is_some(),
+                    "args": room.args,
+                })
+            })
+            .collect();
+        let message = json!({"type": "rooms", "rooms": rooms});
+        for (id, client) in &self.clients {
+            if client.room.is_none() {
+                self.send(*id, &message);
+            }
+        }
+    }
+
+    fn announce_room(&self, id: usize) {
+        let room = match self.rooms.get(&id) {
+            Some(room) => room,
+            None => return,
+        };
+        let players: Vec<Value> = room
+            .players
+            .iter()
+            .zip(room.ready.iter())
+            .map(|(client, ready)| json!({"name": self.name(*client), "ready": ready}))
+            .collect();
+        for (i, client) in room.players.iter().enumerate() {
+            self.send(
+                *client,
+                &json!({
+                    "type": "room",
+                    "id": id,
+                    "name": room.name,
+                    "size": room.size,
+                    "args": room.args,
+                    "players": players,
+                    "playing": room.game.is_some(),
+                    "you": i,
+                }),
+            );
+        }
+    }
+
+    fn relay(&self, room: usize, from: usize, message: &Value) {
+        //To everyone else still in the room who was in the game
+        let room = &self.rooms[&room];
+        if let Some(game) = &room.game {
+            for (i, client) in game.players.iter().enumerate() {
+                if i != from && room.players.contains(client) {
+                    self.send(*client, message);
+                }
+            }
+        }
+    }
+
+    fn playing(&self, client: usize) -> Option<(usize, usize)> {
+        //The room and the player number of someone in a game
+        let room = self.clients.get(&client)?.room?;
+        let game = self.rooms[&room].game.as_ref()?;
+        Some((room, game.players.iter().position(|c| *c == client)?))
+    }
+
+    fn create(&mut self, client: usize, message: &Value) {
+        let size = message["size"].as_u64().unwrap_or(2) as usize;
+        let size = size.clamp(MIN_PLAYERS, MAX_PLAYERS);
+        let id = self.next_room;
+        self.next_room += 1;
+
+        let name = format!("{}'s room", self.name(client));
+        eprintln!("{} opened for {}", name, size);
+        self.rooms.insert(
+            id,
+            Room {
+                name,
+                size,
+                args: rules(&message["args"]),
+                players: vec![client],
+                ready: vec![false],
+                game: None,
+            },
+        );
+        self.clients.get_mut(&client).unwrap().room = Some(id);
+        self.announce_room(id);
+        self.announce_rooms();
+    }
+
+    fn join(&mut self, client: usize, id: usize) {
+        let room = match self.rooms.get_mut(&id) {
+            Some(room) => room,
+            None => return self.error(client, "That room is gone"),
+        };
+        if room.game.is_some() {
+            return self.error(client, "That room is in the middle of a game");
+        }
+        if room.players.len() >= room.size {
+            return self.error(client, "That room is full");
+        }
+        room.players.push(client);
+        room.ready.push(false);
+        self.clients.get_mut(&client).unwrap().room = Some(id);
+        self.announce_room(id);
+        self.announce_rooms();
+    }
+
+    fn leave(&mut self, client: usize) {
+        let id = match self.clients.get_mut(&client).and_then(|c| c.room.take()) {
+            Some(id) => id,
+            None => return,
+        };
+        let game = self.rooms[&id].game.as_ref();
+        if let Some(i) = game.and_then(|g| g.players.iter().position(|c| *c == client)) {
+            self.out(id, i);
+        }
+
+        let room = self.rooms.get_mut(&id).unwrap();
+        if let Some(i) = room.players.iter().position(|c| *c == client) {
+            room.players.remove(i);
+            room.ready.remove(i);
+        }
+        if room.players.is_empty() {
+            eprintln!("{} closed", room.name);
+            self.rooms.remove(&id);
+        } else {
+            self.announce_room(id);
+            //Someone who wasn't ready leaving can be what everyone else was waiting on
+            self.start(id);
+        }
+        self.send(client, &json!({"type": "left"}));
+        self.announce_rooms();
+    }
+
+    fn ready(&mut self, client: usize, ready: bool) {
+        let id = match self.clients.get(&client).and_then(|c| c.room) {
+            Some(id) => id,
+            None => return,
+        };
+        let room = self.rooms.get_mut(&id).unwrap();
+        if room.game.is_some() {
+            return;
+        }
+        if let Some(i) = room.players.iter().position(|c| *c == client) {
+            room.ready[i] = ready;
+        }
+        self.announce_room(id);
+        self.start(id);
+    }
+
+    fn start(&mut self, id: usize) {
+        //Once there's enough people and all of them are ready
+        let room = self.rooms.get_mut(&id).unwrap();
+        if room.game.is_some() || room.players.len() < MIN_PLAYERS || room.ready.contains(&false) {
+            return;
+        }
+        let seed = thread_rng().gen_range(0, 1_000_000u64);
+        room.ready.iter_mut().for_each(|r| *r = false);
+        room.game = Some(Match {
+            players: room.players.clone(),
+            alive: vec![true; room.players.len()],
+        });
+        eprintln!("{} started with seed {}", room.name, seed);
+
+        let room = &self.rooms[&id];
+        let names: Vec<String> = room.players.iter().map(|c| self.name(*c)).collect();
+        for (i, client) in room.players.iter().enumerate() {
+            self.send(
+                *client,
+                &json!({
+                    "type": "start",
+                    "seed": seed,
+                    "args": room.args,
+                    "players": names,
+                    "you": i,
+                }),
+            );
+        }
+        self.announce_rooms();
+    }
+
+    fn attack(&self, client: usize, rows: usize) {
+        //Each attack goes to someone still standing, picked at random
+        let (id, from) = match self.playing(client) {
+            Some(playing) => playing,
+            None => return,
+        };
+        let game = self.rooms[&id].game.as_ref().unwrap();
+        let targets: Vec<usize> = (0..game.players.len())
+            .filter(|i| *i != from && game.alive[*i])
+            .collect();
+        if targets.is_empty() || !game.alive[from] {
+            return;
+        }
+        let target = targets[thread_rng().gen_range(0, targets.len())];
+        self.send(
+            game.players[target],
+            &json!({"type": "attack", "rows": rows, "from": from}),
+        );
+    }
+
+    fn out(&mut self, id: usize, player: usize) {
+        let room = self.rooms.get_mut(&id).unwrap();
+        let game = room.game.as_mut().unwrap();
+        if !game.alive[player] {
+            return;
+        }
+        game.alive[player] = false;
+        let left = game.alive.iter().filter(|a| **a).count();
+        let message = json!({"type": "out", "player": player, "place": left + 1});
+        let winner = game.alive.iter().position(|a| *a);
+        let players = game.players.clone();
+        for client in &players {
+            self.send(*client, &message);
+        }
+
+        //The last one standing wins, and the room goes back to readying up for another
+        if left <= 1 {
+            let room = self.rooms.get_mut(&id).unwrap();
+            room.game = None;
+            let winner_name =
+                winner.map_or_else(|| String::from("nobody"), |w| self.name(players[w]));
+            eprintln!("{} won in {}", winner_name, self.rooms[&id].name);
+            let message = json!({"type": "result", "winner": winner, "name": winner_name});
+            for client in &players {
+                self.send(*client, &message);
+            }
+            self.announce_room(id);
+            self.announce_rooms();
+        }
+    }
+
+    fn handle(&mut self, client: usize, message: &Value) {
+        match message["type"].as_str() {
+            Some("list") => self.announce_rooms(),
+            Some("create") if self.clients[&client].room.is_none() => self.create(client, message),
+            Some("join") if self.clients[&client].room.is_none() => {
+                self.join(client, message["room"].as_u64().unwrap_or(u64::MAX) as usize)
+            }
+            Some("leave") => self.leave(client),
+            Some("ready") => self.ready(client, message["ready"].as_bool().unwrap_or(true)),
+            Some("attack") => self.attack(client, message["rows"].as_u64().unwrap_or(0) as usize),
+            Some("board") => {
+                if let Some((id, from)) = self.playing(client) {
+                    let mut message = message.clone();
+                    message["from"] = json!(from);
+                    self.relay(id, from, &message);
+                }
+            }
+            Some("lost") | Some("quit") => {
+                if let Some((id, player)) = self.playing(client) {
+                    self.out(id, player);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn serve(lobby: Arc<Mutex<Lobby>>, stream: TcpStream) {
+    let address = stream
+        .peer_addr()
+        .map_or_else(|_| String::from("someone"), |a| a.to_string());
+    let (writer, hangup) = match (stream.try_clone(), stream.try_clone()) {
+        (Ok(writer), Ok(hangup)) => (writer, hangup),
+        _ => return,
+    };
+    let _ = stream.set_nodelay(true);
+    let _ = writer.set_write_timeout(Some(Duration::from_secs(1)));
+
+    //Ends once the client is gone from the lobby and nothing can send to it
+    let (outgoing, queue) = mpsc::sync_channel::<String>(QUEUE);
+    thread::spawn(move || {
+        for line in queue {
+            if (&writer).write_all(line.as_bytes()).is_err() {
+                //Half a line would look like garbage to the client, so it's hung up on instead
+                let _ = writer.shutdown(Shutdown::Both);
+                break;
+            }
+        }
+    });
+    let mut lines = BufReader::new(stream).lines();
+
+    //Nothing happens until they say who they are
+    let hello = lines.next().and_then(|l| l.ok());
+    let hello: Value = match hello.and_then(|l| serde_json::from_str(&l).ok()) {
+        Some(hello) => hello,
+        None => return,
+    };
+    let name: String = hello["name"].as_str().unwrap_or("").trim().chars().take(MAX_NAME).collect();
+    let name = if name.is_empty() { String::from("player") } else { name };
+    eprintln!("{} joined from {}", name, address);
+
+    let id = {
+        let mut lobby = lobby.lock().unwrap();
+        let id = lobby.next_client;
+        lobby.next_client += 1;
+        lobby.clients.insert(
+            id,
+            Client {
+                name: name.clone(),
+                outgoing,
+                stream: hangup,
+                room: None,
+            },
+        );
+        lobby.send(id, &json!({"type": "welcome", "name": name}));
+        lobby.announce_rooms();
+        id
+    };
+
+    for line in lines {
+        let message: Value = match line.ok().and_then(|l| serde_json::from_str(&l).ok()) {
+            Some(message) => message,
+            None => break,
+        };
+        lobby.lock().unwrap().handle(id, &message);
+    }
+
+    let mut lobby = lobby.lock().unwrap();
+    lobby.leave(id);
+    lobby.clients.remove(&id);
+    eprintln!("{} left", name);
+}
+
+fn main() {
+    let mut port = PORT;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .and_then(|port| port.parse().ok())
+                    .expect("--port takes a number");
+            }
+            _ => {
+                eprintln!("lobby only takes --port N");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let listener = match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on port {}: {}", port, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Lobby open on port {}", port);
+
+    let lobby = Arc::new(Mutex::new(Lobby::new()));
+    for stream in listener.incoming().flatten() {
+        let lobby = lobby.clone();
+        thread::spawn(move || serve(lobby, stream));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::game::End;
+use crate::{Action, Board, Direction, LockResult, Options, Player, Windows};
+use ncurses::*;
+use rand::{thread_rng, Rng};
+use std::{thread, time};
+
+//Co-op is played on a field this wide unless --width says otherwise
+pub const WIDTH: usize = 20;
+
+//One player's piece and how long it's been falling, everything else is on the shared board
+struct Seat {
+    player: Player,
+    time: usize,
+    lock: bool,
+    lock_time: usize,
+    //A new piece waits at the top until the other player's piece is out of its way
+    waiting: bool,
+}
+
+//Two people on one field with a piece each, taking turns from the same queue. Each piece is in
+//the other's way, and lines clear for both.
+pub struct Coop {
+    pub board: Board,
+    seats: [Seat; 2],
+}
+
+impl Coop {
+    pub fn new(options: &Options, seed: u64) -> Coop {
+        //Nothing to hold with, the one HELD box couldn't show both
+        let mut rules = options.rules;
+        rules.hold = false;
+        let board = Board::new(rules, options.pieces.clone(), options.level, seed);
+        let seat = |player| Seat {
+            player,
+            time: 0,
+            lock: false,
+            lock_time: 0,
+            waiting: false,
+        };
+        let mut coop = Coop {
+            seats: [seat(board.player), seat(board.player)],
+            board,
+        };
+        coop.seats[0].player = coop.spawn(0, coop.board.player.shape);
+        coop.board.get_new_piece();
+        coop.seats[1].player = coop.spawn(1, coop.board.player.shape);
+        coop
+    }
+
+    fn spawn(&self, seat: usize, shape: crate::pieces::Shape) -> Player {
+        //Each piece starts in the middle of its player's half
+        let half = self.board.rules.width as isize / 2;
+        let size = self.board.pieces.size(shape) as isize;
+        Player {
+            x: half * seat as isize + (half - size) / 2,
+            ..self.board.spawn(shape)
+        }
+    }
+
+    fn sit(&mut self, seat: usize) {
+        //Makes this seat's piece the board's player, with the other one in its way. Lines
+        //cleared under the other piece can bring the stack down onto it, so it's pushed up.
+        self.board.others.clear();
+        let mut player = self.seats[seat].player;
+        while self.board.collision(&player) && player.y > 0 {
+            player.y -= 1;
+        }
+        self.board.player = player;
+        if !self.seats[1 - seat].waiting {
+            self.board.others.push(self.seats[1 - seat].player);
+        }
+    }
+
+    fn arrive(&mut self, seat: usize) -> Option<End> {
+        //Only the stack being in the way ends the game, the other player's piece just holds
+        //the new one back until it moves
+        let player = self.seats[seat].player;
+        let rows = self.board.pieces.rows(player.shape, player.orientation);
+        if self.board.field.collides(player.y, player.x, rows) {
+            return Some(End::LockOut);
+        }
+        self.board.others = vec![self.seats[1 - seat].player];
+        self.seats[seat].waiting = self.board.blocked(&player);
+        None
+    }
+
+    fn resting(&mut self) -> bool {
+        //Whether the piece is on the stack rather than on the other falling piece
+        let others = std::mem::take(&mut self.board.others);
+        let resting = self.board.moved(&self.board.player, Direction::S).is_none();
+        self.board.others = others;
+        resting
+    }
+
+    pub fn tick(&mut self, actions: [Option<Action>; 2]) -> Option<End> {
+        for (i, action) in actions.iter().enumerate() {
+            if !self.seats[i].waiting {
+                self.sit(i);
+                let end = self.turn(i, *action);
+                self.seats[i].player = self.board.player;
+                if end.is_some() {
+                    return end;
+                }
+            }
+            if self.seats[i].waiting {
+                let end = self.arrive(i);
+                if end.is_some() {
+                    return end;
+                }
+            }
+        }
+        None
+    }
+
+    fn turn(&mut self, i: usize, action: Option<Action>) -> Option<End> {
+        //The same as a tick of Game, for one seat and without hold or garbage
+        let board = &mut self.board;
+        let seat = &mut self.seats[i];
+
+        let set_lock = match action {
+            Some(Action::HardDrop) if board.rules.hard_drop => {
+                seat.lock_time = 40;
+                board.hard_drop()
+            }
+            Some(Action::Left) => board.move_player(Direction::W),
+            Some(Action::Right) => board.move_player(Direction::E),
+            Some(Action::SoftDrop) => board.move_player(Direction::S),
+            Some(Action::RotateCCW) => board.rotate_player(Direction::W),
+            Some(Action::RotateCW) => board.rotate_player(Direction::E),
+            _ => LockResult::NoChange,
+        };
+        match set_lock {
+            LockResult::Lock => seat.lock = true,
+            LockResult::Unlock => {
+                seat.lock = false;
+                seat.lock_time = 0;
+            }
+            LockResult::NoChange => {}
+        }
+
+        if seat.time >= board.gravity() && !seat.lock {
+            seat.time = 0;
+            if let LockResult::Lock = board.move_player(Direction::S) {
+                seat.lock_time = 0;
+                seat.lock = true;
+            }
+        }
+        seat.time += 1;
+
+        if !seat.lock {
+            return None;
+        }
+        seat.lock_time += 1;
+        if seat.lock_time < board.rules.lock_delay {
+            return None;
+        }
+        seat.lock = false;
+        seat.lock_time = 0;
+        seat.time = 0;
+
+        //Held up by the other piece, it waits to fall once that one's out of the way
+        if !self.resting() {
+            return None;
+        }
+
+        let board = &mut self.board;
+        board.lock();
+        if !board.check_lines() {
+            return Some(End::LockOut);
+        }
+        board.get_new_piece();
+        self.board.player = self.spawn(i, self.board.player.shape);
+        self.seats[i].waiting = true;
+        None
+    }
+
+    fn draw(&mut self, windows: &Windows) {
+        //Drawn from the first seat, the second one's piece and ghost are drawn as one of the others.
+        //A piece that's waiting to come in isn't on the field yet, so it's drawn from the other.
+        self.sit(if self.seats[0].waiting { 1 } else { 0 });
+        self.board.draw(windows);
+        self.board.draw_next(windows);
+        self.board.draw_score(windows);
+    }
+}
+
+pub fn play(options: &Options) -> Coop {
+    let seed = options
+        .seed
+        .unwrap_or_else(|| thread_rng().gen_range(0, 1_000_000));
+    let mut coop = Coop::new(options, seed);
+    let windows = Windows::new(&coop.board.rules, &options.pieces);
+
+    let ten_millis = time::Duration::from_millis(10);
+
+    loop {
+        let key = getch();
+        if key == 27 {
+            break;
+        }
+
+        let actions = [Action::from_left_key(key), Action::from_right_key(key)];
+        let end = coop.tick(actions);
+
+        coop.draw(&windows);
+        if end.is_some() {
+            break;
+        }
+        thread::sleep(ten_millis);
+    }
+
+    coop
+}
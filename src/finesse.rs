@@ -0,0 +1,90 @@
+use crate::{Board, Direction, Player};
+use ncurses::*;
+use std::collections::{HashSet, VecDeque};
+
+#[derive(Clone)]
+pub struct Finesse {
+    pub warn: bool,
+    pub training: bool,
+    pub inputs: usize,
+    flash: usize,
+}
+
+impl Finesse {
+    pub fn new(warn: bool, training: bool) -> Finesse {
+        Finesse {
+            warn: warn || training,
+            training,
+            inputs: 0,
+            flash: 0,
+        }
+    }
+
+    pub fn input(&mut self) {
+        self.inputs += 1;
+    }
+
+    pub fn reset(&mut self) {
+        self.inputs = 0;
+    }
+
+    pub fn minimal_inputs(board: &Board, placement: &Player) -> Option<usize> {
+        //Breadth first over taps and rotations from spawn, each dropped straight down.
+        //Placements that need a soft drop, tuck or spin can't be judged and give None.
+        let target = board.cells(placement);
+        let spawn = board.spawn(placement.shape);
+
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert((spawn.x, spawn.y, spawn.orientation));
+        queue.push_back((spawn, 0));
+
+        while let Some((player, depth)) = queue.pop_front() {
+            if board.cells(&board.dropped(&player)) == target {
+                return Some(depth);
+            }
+
+            let next = [
+                board.moved(&player, Direction::W),
+                board.moved(&player, Direction::E),
+                board.rotated(&player, Direction::W),
+                board.rotated(&player, Direction::E),
+            ];
+            for p in next.iter().flatten() {
+                if seen.insert((p.x, p.y, p.orientation)) {
+                    queue.push_back((*p, depth + 1));
+                }
+            }
+        }
+        None
+    }
+
+    pub fn check(&mut self, board: &Board) -> bool {
+        //Returns true if the piece about to lock took more inputs than it needed
+        let fault = match Finesse::minimal_inputs(board, &board.player) {
+            Some(minimal) => self.inputs > minimal,
+            None => false,
+        };
+
+        if fault && self.warn {
+            self.flash = 100;
+        }
+        self.reset();
+        fault
+    }
+
+    pub fn draw(&mut self, window: WINDOW) {
+        if self.flash == 0 {
+            return;
+        }
+
+        self.flash -= 1;
+        werase(window);
+        if self.flash > 0 {
+            wattron(window, COLOR_PAIR(5) | A_BOLD());
+            mvwprintw(window, 0, 1, if self.training { " RETRY!" } else { "FINESSE" });
+            wattroff(window, COLOR_PAIR(5) | A_BOLD());
+        }
+        wrefresh(window);
+    }
+}
@@ -0,0 +1,279 @@
+use crate::attack::{self, Attack};
+use crate::finesse::Finesse;
+use crate::stats::Stats;
+use crate::{Action, Board, Direction, LockResult, Mode, Options};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hash::{Hash, Hasher};
+
+//Why a game stopped
+#[derive(Copy, Clone, PartialEq)]
+pub enum End {
+    LockOut,
+    Garbage,
+}
+
+impl End {
+    pub fn name(self) -> &'static str {
+        match self {
+            End::LockOut => "lock-out",
+            End::Garbage => "garbage",
+        }
+    }
+}
+
+fn survival_interval(rows: usize) -> usize {
+    //Ticks between garbage rows, shrinking as the game goes on
+    if rows * 25 >= 850 {
+        150
+    } else {
+        1000 - rows * 25
+    }
+}
+
+//FNV-1a, which unlike the standard library's hasher is the same in every build. Numbers go in as
+//little endian and sizes as 64 bits, so 32 bit and big endian machines agree too.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+}
+
+//Everything that changes from one 10ms tick to the next, with nothing tied to the terminal.
+//Nothing but the actions passed to tick changes it, so a clone is a snapshot to go back to.
+#[derive(Clone)]
+pub struct Game {
+    pub board: Board,
+    pub stats: Stats,
+    pub finesse: Finesse,
+    pub attack: Attack,
+    pub can_hold: bool,
+    pub ticks: usize,
+    mode: Mode,
+    time: usize,
+    lock_time: usize,
+    lock: bool,
+    //Whether the last thing that moved the piece was a rotation, for spotting T-spins
+    rotated: bool,
+    garbage_time: usize,
+    pub garbage_rows: usize,
+    garbage_rng: StdRng,
+}
+
+impl Game {
+    pub fn new(options: &Options, seed: u64) -> Game {
+        let board = Board::new(options.rules, options.pieces.clone(), options.level, seed);
+        let stats = Stats::new(board.pieces.len());
+
+        Game {
+            board,
+            stats,
+            finesse: Finesse::new(options.finesse, options.finesse_training),
+            attack: Attack::new(),
+            can_hold: true,
+            ticks: 0,
+            mode: options.mode,
+            time: 0,
+            lock_time: 0,
+            lock: false,
+            rotated: false,
+            garbage_time: 0,
+            garbage_rows: 0,
+            //Holes come from their own stream so they don't change the pieces
+            garbage_rng: StdRng::seed_from_u64(!seed),
+        }
+    }
+
+    pub fn hash(&self) -> u64 {
+        //Two games that were played the same way hash the same, on any machine
+        let mut hasher = Fnv::new();
+        let board = &self.board;
+        for y in 0..board.rows() {
+            board.field.row(y).hash(&mut hasher);
+        }
+        let player = &board.player;
+        (player.shape.0, player.orientation, player.y, player.x).hash(&mut hasher);
+        board.held.map(|s| s.0).hash(&mut hasher);
+        for shape in board.grab_bag.iter() {
+            shape.0.hash(&mut hasher);
+        }
+        (board.lines, board.score, board.locked).hash(&mut hasher);
+        (self.ticks, self.time, self.lock_time, self.lock, self.can_hold).hash(&mut hasher);
+        (self.garbage_time, self.garbage_rows).hash(&mut hasher);
+        self.attack.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn tick(&mut self, action: Option<Action>) -> Option<End> {
+        //Returns why the game ended if this was the last tick
+        let board = &mut self.board;
+
+        if action.is_some() {
+            self.stats.keys += 1;
+        }
+
+        let y = board.player.y;
+        let mut set_lock = match action {
+            Some(Action::Hold) => {
+                if self.can_hold && board.rules.hold {
+                    board.hold();
+                    self.finesse.reset();
+                    self.can_hold = false;
+                    LockResult::Unlock
+                } else {
+                    LockResult::NoChange
+                }
+            }
+            Some(Action::HardDrop) if board.rules.hard_drop => {
+                self.lock_time = 40;
+                board.hard_drop()
+            }
+            Some(Action::Left) => {
+                self.finesse.input();
+                board.move_player(Direction::W)
+            }
+            Some(Action::Right) => {
+                self.finesse.input();
+                board.move_player(Direction::E)
+            }
+            Some(Action::SoftDrop) => board.move_player(Direction::S),
+            Some(Action::RotateCCW) => {
+                self.finesse.input();
+                board.rotate_player(Direction::W)
+            }
+            Some(Action::RotateCW) => {
+                self.finesse.input();
+                board.rotate_player(Direction::E)
+            }
+            _ => LockResult::NoChange,
+        };
+
+        //Moving or dropping the piece at all means it wasn't spun in
+        match (action, &set_lock) {
+            (Some(Action::RotateCCW | Action::RotateCW), LockResult::Unlock) => self.rotated = true,
+            (Some(Action::HardDrop), _) if board.player.y != y => self.rotated = false,
+            (Some(_), LockResult::Unlock) => self.rotated = false,
+            _ => {}
+        }
+
+        match set_lock {
+            LockResult::Lock => {
+                self.lock = true;
+            }
+            LockResult::Unlock => {
+                self.lock = false;
+                self.lock_time = 0;
+            }
+            LockResult::NoChange => {}
+        }
+
+        if self.time >= board.gravity() && !self.lock {
+            self.time = 0;
+            set_lock = board.move_player(Direction::S);
+            match set_lock {
+                LockResult::Lock => {
+                    self.lock_time = 0;
+                    self.lock = true;
+                }
+                LockResult::Unlock => self.rotated = false,
+                LockResult::NoChange => {}
+            }
+        }
+
+        if self.lock {
+            self.lock_time += 1;
+            if self.lock_time >= board.rules.lock_delay {
+                let fault = self.finesse.check(board);
+                if fault {
+                    self.stats.faults += 1;
+                }
+
+                if fault && self.finesse.training {
+                    //Training sends the piece back to the top to try again
+                    board.player = board.spawn(board.player.shape);
+                } else {
+                    let spin = attack::spin(board, &board.player, self.rotated);
+                    let lines = board.lines;
+                    board.lock();
+                    self.stats.piece_locked(board.player.shape);
+
+                    if !board.check_lines() {
+                        return Some(End::LockOut);
+                    }
+                    let cleared = board.lines - lines;
+                    let perfect = cleared > 0 && !board.field.any_filled(0..board.rows());
+                    self.attack.cleared(cleared, spin, perfect);
+
+                    board.get_new_piece();
+                    self.can_hold = true;
+
+                    //Garbage only comes up when the piece didn't clear anything
+                    if cleared == 0 {
+                        for rows in self.attack.take_incoming() {
+                            let hole = self.garbage_rng.gen_range(0, board.rules.width);
+                            for _ in 0..rows {
+                                if !board.insert_garbage(hole) {
+                                    return Some(End::Garbage);
+                                }
+                            }
+                        }
+                    }
+                }
+                self.rotated = false;
+                self.lock = false;
+                self.lock_time = 0;
+                self.time = 0;
+            }
+        }
+
+        if self.mode == Mode::Survival {
+            self.garbage_time += 1;
+            if self.garbage_time >= survival_interval(self.garbage_rows) {
+                self.garbage_time = 0;
+                self.garbage_rows += 1;
+                let hole = self.garbage_rng.gen_range(0, board.rules.width);
+                if !board.insert_garbage(hole) {
+                    return Some(End::Garbage);
+                }
+            }
+        }
+
+        self.time += 1;
+        self.ticks += 1;
+        None
+    }
+}
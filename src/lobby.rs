@@ -0,0 +1,394 @@
+use crate::ai::Bot;
+use crate::attack::Attack;
+use crate::game::Game;
+use crate::net::{apply, draw, snapshot, Peer};
+use crate::{
+    ordinal, parse_options, shared_rules, start_terminal, try_options, Action, Board, Controller,
+    Options, Windows,
+};
+use ncurses::*;
+use serde_json::{json, Value};
+use std::net::TcpStream;
+use std::sync::mpsc::TryRecvError;
+use std::{thread, time};
+
+//The client for the lobby server in src/bin/lobby.rs, which does the matchmaking and passes
+//attacks and boards around. Games themselves are played here.
+
+pub const PORT: u16 = 7779;
+
+//Ticks between the board pictures sent to the rest of the room
+const SNAPSHOT_TICKS: usize = 5;
+
+//How a lobby game ended, from this side
+enum Ending {
+    Won,
+    Placed(usize),
+    Quit,
+    Disconnected,
+}
+
+fn play(
+    options: &Options,
+    peer: &mut Peer,
+    seed: u64,
+    names: &[String],
+    you: usize,
+) -> (Ending, Game) {
+    clear();
+    refresh();
+    let windows = [
+        Windows::at(&options.rules, &options.pieces, COLS() / 4, "YOU"),
+        Windows::at(&options.rules, &options.pieces, COLS() * 3 / 4, "THEM"),
+    ];
+    let mut game = Game::new(options, seed);
+    let mut them = Board::new(options.rules, options.pieces.clone(), options.level, seed);
+    let mut their_attack = Attack::new();
+
+    let mut controller = if options.ai {
+        Controller::Bot(Bot::new(options.weights))
+    } else {
+        Controller::Keys(Action::from_key)
+    };
+
+    //The other board shown is whoever attacked last, until they're out
+    let mut alive = vec![true; names.len()];
+    let mut watched = (you + 1) % names.len();
+    let mut seen = false;
+    let mut place = None;
+
+    let ten_millis = time::Duration::from_millis(10);
+
+    let result = loop {
+        let key = getch();
+        if key == 27 {
+            peer.send(&json!({"type": "quit"}));
+            break Ending::Quit;
+        }
+
+        //Once out this side just watches until there's a winner
+        if place.is_none() {
+            let action = controller.action(key, &game);
+            if game.tick(action).is_some() {
+                peer.send(&json!({"type": "lost"}));
+                place = Some(alive.iter().filter(|a| **a).count());
+            }
+
+            let sent = game.attack.take_outgoing();
+            if sent > 0 {
+                peer.send(&json!({"type": "attack", "rows": sent}));
+            }
+            if game.ticks.is_multiple_of(SNAPSHOT_TICKS) {
+                peer.send(&snapshot(&game));
+            }
+        }
+
+        let mut over = None;
+        loop {
+            let message = match peer.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    over.get_or_insert(Ending::Disconnected);
+                    break;
+                }
+            };
+            let from = message["from"].as_u64().map_or(you, |f| f as usize);
+            match message["type"].as_str() {
+                Some("attack") => {
+                    if place.is_none() {
+                        game.attack.receive(message["rows"].as_u64().unwrap_or(0) as usize);
+                    }
+                    if from != watched && from < names.len() && alive[from] {
+                        watched = from;
+                        seen = false;
+                    }
+                }
+                Some("board") if from == watched => {
+                    seen = apply(&mut them, &mut their_attack, &message).is_some();
+                }
+                Some("out") => {
+                    let player = message["player"].as_u64().unwrap_or(0) as usize;
+                    if player < names.len() {
+                        alive[player] = false;
+                    }
+                    if player == you {
+                        place = message["place"].as_u64().map(|p| p as usize).or(place);
+                    } else if player == watched {
+                        let next = (1..names.len())
+                            .map(|i| (watched + i) % names.len())
+                            .find(|p| *p != you && alive[*p]);
+                        watched = next.unwrap_or(watched);
+                        seen = false;
+                    }
+                }
+                Some("result") => {
+                    over = Some(match message["winner"].as_u64() {
+                        Some(winner) if winner as usize == you => Ending::Won,
+                        _ => Ending::Placed(place.unwrap_or(2)),
+                    });
+                }
+                _ => {}
+            }
+        }
+        if let Some(result) = over {
+            break result;
+        }
+
+        draw(&windows[0], &game.board, &game.attack);
+        game.finesse.draw(windows[0].message);
+        if seen {
+            draw(&windows[1], &them, &their_attack);
+        }
+        let left = alive.iter().filter(|a| **a).count();
+        mvwprintw(windows[1].message, 0, 0, &format!("{} ({} left)", names[watched], left));
+        wclrtoeol(windows[1].message);
+        wrefresh(windows[1].message);
+
+        thread::sleep(ten_millis);
+    };
+
+    drop(windows);
+    clear();
+    refresh();
+    (result, game)
+}
+
+fn draw_lobby(address: &str, name: &str, rooms: &[Value], note: &str) {
+    clear();
+    mvprintw(1, 2, &format!("LOBBY  {}  playing as {}", address, name));
+    mvprintw(3, 4, &format!("{:<24}{:<9}", "ROOM", "PLAYERS"));
+    for (i, room) in rooms.iter().take(26).enumerate() {
+        let args: Vec<&str> = room["args"]
+            .as_array()
+            .map(|args| args.iter().filter_map(|a| a.as_str()).collect())
+            .unwrap_or_default();
+        let playing = if room["playing"] == true { "playing" } else { "waiting" };
+        let players = format!("{}/{}", room["players"], room["size"]);
+        let line = format!(
+            "{} {:<24}{:<9}{:<9}{}",
+            (b'a' + i as u8) as char,
+            room["name"].as_str().unwrap_or(""),
+            players,
+            playing,
+            args.join(" ")
+        );
+        mvprintw(4 + i as i32, 2, &line);
+    }
+    if rooms.is_empty() {
+        mvprintw(4, 4, "No rooms yet");
+    }
+
+    let y = 6 + std::cmp::max(rooms.len(), 1) as i32;
+    mvprintw(y, 2, "a-z joins a room, 2-8 opens one for that many players, escape leaves");
+    mvprintw(y + 2, 2, note);
+    refresh();
+}
+
+fn draw_room(room: &Value, note: &str) {
+    clear();
+    let players = room["players"].as_array().cloned().unwrap_or_default();
+    let args: Vec<&str> = room["args"]
+        .as_array()
+        .map(|args| args.iter().filter_map(|a| a.as_str()).collect())
+        .unwrap_or_default();
+    let title = format!(
+        "{}  {}/{}  {}",
+        room["name"].as_str().unwrap_or(""),
+        players.len(),
+        room["size"],
+        args.join(" ")
+    );
+    mvprintw(1, 2, &title);
+    for (i, player) in players.iter().enumerate() {
+        let ready = if player["ready"] == true { "READY" } else { "" };
+        let you = if room["you"] == i { "*" } else { " " };
+        let line = format!("{} {:<18}{}", you, player["name"].as_str().unwrap_or(""), ready);
+        mvprintw(3 + i as i32, 2, &line);
+    }
+
+    let y = 4 + players.len() as i32;
+    if room["playing"] == true {
+        mvprintw(y, 2, "Waiting for the game in progress to finish");
+    } else {
+        mvprintw(y, 2, "r to get ready or not, escape leaves the room");
+        mvprintw(y + 1, 2, "The game starts once there are two of you and everyone is ready");
+    }
+    mvprintw(y + 3, 2, note);
+    refresh();
+}
+
+pub fn run(args: Vec<String>) {
+    let mut name = std::env::var("USER").unwrap_or_else(|_| String::from("player"));
+    let mut rest = Vec::new();
+    let mut args = args.into_iter().peekable();
+
+    //The address comes first if there is one, like join
+    let address = match args.peek() {
+        Some(address) if !address.starts_with("--") => {
+            let address = args.next().unwrap();
+            if address.contains(':') {
+                address
+            } else {
+                format!("{}:{}", address, PORT)
+            }
+        }
+        _ => format!("localhost:{}", PORT),
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--name" => name = args.next().expect("--name takes a name"),
+            _ => rest.push(arg),
+        }
+    }
+    //Checked now rather than when a room is opened with them
+    let mine = parse_options(rest.clone());
+
+    //Rooms get the rules, how this side plays stays here
+    let rules = shared_rules(&rest);
+
+    let mut peer = match TcpStream::connect(&address)
+        .map_err(|e| e.to_string())
+        .and_then(Peer::new)
+    {
+        Ok(peer) => peer,
+        Err(e) => {
+            eprintln!("Could not connect to {}: {}", address, e);
+            std::process::exit(1);
+        }
+    };
+    peer.send(&json!({"type": "hello", "name": name}));
+    match peer.recv().filter(|m| m["type"] == "welcome") {
+        Some(welcome) => name = welcome["name"].as_str().unwrap_or(&name).to_string(),
+        None => {
+            eprintln!("{} isn't a lobby", address);
+            std::process::exit(1);
+        }
+    }
+
+    start_terminal();
+
+    let mut rooms = Vec::new();
+    let mut room: Option<Value> = None;
+    let mut note = String::new();
+    let mut changed = true;
+
+    let ten_millis = time::Duration::from_millis(10);
+
+    let disconnected = loop {
+        if changed {
+            match &room {
+                Some(room) => draw_room(room, &note),
+                None => draw_lobby(&address, &name, &rooms, &note),
+            }
+            changed = false;
+        }
+
+        let key = getch();
+        match &room {
+            Some(_) if key == 27 => {
+                peer.send(&json!({"type": "leave"}));
+            }
+            Some(room) if key == 'r' as i32 => {
+                let you = room["you"].as_u64().unwrap_or(0) as usize;
+                let ready = room["players"][you]["ready"] == true;
+                peer.send(&json!({"type": "ready", "ready": !ready}));
+            }
+            None if key == 27 => break false,
+            None if ('a' as i32..='z' as i32).contains(&key) => {
+                if let Some(chosen) = rooms.get((key - 'a' as i32) as usize) {
+                    peer.send(&json!({"type": "join", "room": chosen["id"]}));
+                }
+            }
+            None if ('2' as i32..='8' as i32).contains(&key) => {
+                let size = key - '0' as i32;
+                peer.send(&json!({"type": "create", "size": size, "args": rules}));
+            }
+            _ => {}
+        }
+
+        let mut gone = false;
+        loop {
+            let message = match peer.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    gone = true;
+                    break;
+                }
+            };
+            changed = true;
+            match message["type"].as_str() {
+                //The list only comes to those not in a room
+                Some("rooms") => {
+                    rooms = message["rooms"].as_array().cloned().unwrap_or_default();
+                    room = None;
+                }
+                Some("room") => room = Some(message),
+                Some("error") => note = message["message"].as_str().unwrap_or("").to_string(),
+                Some("start") => {
+                    let strings = |value: &Value| -> Vec<String> {
+                        value.as_array().map_or_else(Vec::new, |values| {
+                            values.iter().map(|v| v.as_str().unwrap_or("").to_string()).collect()
+                        })
+                    };
+                    let rules = strings(&message["args"]);
+                    let seed = message["seed"].as_u64().unwrap_or(0);
+                    //Rules that can't be played here, like a piece file only the host has, sit
+                    //this game out rather than taking the terminal down with them
+                    let mut options = match try_options(rules) {
+                        Ok(options) => options,
+                        Err(e) => {
+                            peer.send(&json!({"type": "quit"}));
+                            note = format!("Couldn't play by this room's rules: {}", e);
+                            continue;
+                        }
+                    };
+                    options.seed = Some(seed);
+                    //Who's playing this side is up to each player, like join
+                    options.ai = mine.ai;
+                    options.weights = mine.weights;
+                    options.finesse = mine.finesse;
+                    options.finesse_training = mine.finesse_training;
+
+                    let names = strings(&message["players"]);
+                    let you = message["you"].as_u64().unwrap_or(0) as usize;
+                    if you >= names.len() {
+                        continue;
+                    }
+
+                    let (result, game) = play(&options, &mut peer, seed, &names, you);
+                    let lines = game.board.lines;
+                    note = match result {
+                        Ending::Won => format!("You won the last game with {} lines!", lines),
+                        Ending::Placed(place) => format!(
+                            "You came {} of {} in the last game with {} lines.",
+                            ordinal(place),
+                            names.len(),
+                            lines
+                        ),
+                        Ending::Quit => format!("You quit the last game with {} lines.", lines),
+                        Ending::Disconnected => {
+                            gone = true;
+                            String::new()
+                        }
+                    };
+                }
+                _ => {}
+            }
+        }
+        if gone {
+            break true;
+        }
+
+        thread::sleep(ten_millis);
+    };
+
+    endwin();
+    if disconnected {
+        println!("Lost the connection to the lobby.");
+    }
+    if !note.is_empty() {
+        println!("{}", note);
+    }
+}
@@ -1,416 +1,786 @@
+mod ai;
+mod attack;
+mod coop;
+mod finesse;
+mod game;
+mod lobby;
+mod net;
+mod pieces;
+mod playfield;
+mod rollback;
+mod royale;
+mod scores;
+mod search;
+mod serve;
+mod sim;
+mod spectate;
+mod stats;
+mod tbp;
+mod tune;
+mod versus;
+
 use ncurses::*;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use game::Game;
+use pieces::{PieceSet, Shape};
+use playfield::Playfield;
+use rand::{thread_rng, Rng, SeedableRng};
+use std::sync::Arc;
 use std::{thread, time};
 
 #[derive(Copy, Clone)]
 enum Direction {
-    N,
     E,
     S,
     W,
 }
 
-#[derive(Copy, Clone)]
-enum Shape {
-    I,
-    O,
-    T,
-    S,
-    Z,
-    J,
-    L,
+//What the player can do to the piece, whoever is pressing the keys
+#[derive(Copy, Clone, PartialEq)]
+enum Action {
+    Left,
+    Right,
+    SoftDrop,
+    HardDrop,
+    RotateCW,
+    RotateCCW,
+    Hold,
+}
+
+impl Action {
+    fn from_key(key: i32) -> Option<Action> {
+        match key {
+            48 | 99 => Some(Action::Hold),
+            56 | 32 => Some(Action::HardDrop),
+            52 | KEY_LEFT => Some(Action::Left),
+            54 | KEY_RIGHT => Some(Action::Right),
+            50 | KEY_DOWN => Some(Action::SoftDrop),
+            51 | 55 | 122 => Some(Action::RotateCCW),
+            49 | 53 | 57 | 120 | KEY_UP => Some(Action::RotateCW),
+            _ => None,
+        }
+    }
+
+    fn from_left_key(key: i32) -> Option<Action> {
+        //WASD with Q and E to rotate, for whoever is on the left in versus
+        match key {
+            102 => Some(Action::Hold),
+            119 => Some(Action::HardDrop),
+            97 => Some(Action::Left),
+            100 => Some(Action::Right),
+            115 => Some(Action::SoftDrop),
+            113 => Some(Action::RotateCCW),
+            101 => Some(Action::RotateCW),
+            _ => None,
+        }
+    }
+
+    fn from_right_key(key: i32) -> Option<Action> {
+        //The arrows and the number pad, leaving the letters to the left side
+        match key {
+            48..=57 | KEY_LEFT | KEY_RIGHT | KEY_DOWN | KEY_UP => Action::from_key(key),
+            _ => None,
+        }
+    }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Copy, Clone)]
 enum Square {
     FULL(Shape),
+    GARBAGE,
     EMPTY,
 }
 
-#[derive(Clone)]
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Marathon,
+    Survival,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Randomizer {
+    Bag,
+    Nes,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum RuleSet {
+    Modern,
+    Classic,
+}
+
+#[derive(Copy, Clone)]
+struct Rules {
+    set: RuleSet,
+    hold: bool,
+    ghost: bool,
+    hard_drop: bool,
+    kicks: bool,
+    randomizer: Randomizer,
+    lock_delay: usize,
+    width: usize,
+    height: usize,
+    buffer: usize,
+    pieces: &'static str,
+}
+
+impl Rules {
+    fn modern() -> Rules {
+        Rules {
+            set: RuleSet::Modern,
+            hold: true,
+            ghost: true,
+            hard_drop: true,
+            kicks: true,
+            randomizer: Randomizer::Bag,
+            lock_delay: 50,
+            width: 10,
+            height: 20,
+            buffer: 20,
+            pieces: "standard",
+        }
+    }
+
+    fn classic() -> Rules {
+        Rules {
+            set: RuleSet::Classic,
+            hold: false,
+            ghost: false,
+            hard_drop: false,
+            kicks: false,
+            randomizer: Randomizer::Nes,
+            lock_delay: 0,
+            width: 10,
+            height: 20,
+            buffer: 20,
+            pieces: "nes",
+        }
+    }
+
+    fn standard_size(&self) -> bool {
+        self.width == 10 && self.height == 20 && self.buffer == 20
+    }
+}
+
+#[derive(Copy, Clone)]
 struct Player {
-    data: Vec<Square>,
     shape: Shape,
-    direction: Direction,
+    orientation: usize,
     y: isize,
     x: isize,
 }
 
+struct Windows {
+    board: WINDOW,
+    next: WINDOW,
+    held: WINDOW,
+    lines: WINDOW,
+    level: WINDOW,
+    score: WINDOW,
+    stats: WINDOW,
+    message: WINDOW,
+    meter: WINDOW,
+}
+
+impl Windows {
+    fn new(rules: &Rules, pieces: &PieceSet) -> Windows {
+        Windows::at(rules, pieces, COLS() / 2, "tetris")
+    }
+
+    fn at(rules: &Rules, pieces: &PieceSet, center: i32, title: &str) -> Windows {
+        //Lays out a board centered on the given column, so more than one can share the screen
+        let max_y = LINES();
+
+        //The board window is the playfield plus its border, everything else hangs off of it
+        let height = rules.height as i32 + 2;
+        let width = rules.width as i32 + 2;
+
+        //NEXT and HELD are as big as the biggest piece in the set
+        let (preview_height, preview_width) = pieces.preview_size();
+        let preview_height = preview_height as i32 + 2;
+        let preview_width = preview_width as i32 + 2;
+        let preview_x = preview_width + 2;
+
+        let y = std::cmp::max((max_y - height) / 2, 2);
+        let x = std::cmp::max(center - width / 2, preview_x);
+        let side = x + width + 2;
+
+        let board = newwin(height, width, y, x);
+        box_(board, 0, 0);
+
+        mvprintw(y - 2, x + (width - title.len() as i32) / 2, title);
+        refresh();
+
+        mvprintw(y + 2, side, "LINES");
+        let lines = newwin(3, 5, y + 3, side);
+        box_(lines, 0, 0);
+        wrefresh(lines);
+
+        mvprintw(y + 7, side, "LEVEL");
+        let level = newwin(3, 5, y + 8, side);
+        box_(level, 0, 0);
+        wrefresh(level);
+
+        mvprintw(y + 12, side, "SCORE");
+        let score = newwin(3, 9, y + 13, side);
+        box_(score, 0, 0);
+        wrefresh(score);
+
+        mvprintw(y + 1, x - preview_x, " NEXT");
+        let next = newwin(preview_height, preview_width, y + 2, x - preview_x);
+        box_(next, 0, 0);
+        wrefresh(next);
+
+        let stats = newwin(9 + pieces.len() as i32, 20, y, side + 11);
+        let message = newwin(1, std::cmp::max(width, 9), y + height, x);
+        //Incoming garbage is shown down the left edge of the playfield
+        let meter = newwin(height - 2, 1, y + 1, x - 1);
+
+        let held_y = y + preview_height + 5;
+        let held = newwin(preview_height, preview_width, held_y, x - preview_x);
+        if rules.hold {
+            mvprintw(held_y - 1, x - preview_x, " HELD");
+            box_(held, 0, 0);
+            wrefresh(held);
+        }
+
+        Windows {
+            board,
+            next,
+            held,
+            lines,
+            level,
+            score,
+            stats,
+            message,
+            meter,
+        }
+    }
+}
+
+impl Drop for Windows {
+    fn drop(&mut self) {
+        //Lobby games come and go without the terminal being put back in between
+        let windows = [
+            self.board,
+            self.next,
+            self.held,
+            self.lines,
+            self.level,
+            self.score,
+            self.stats,
+            self.message,
+            self.meter,
+        ];
+        for window in windows {
+            delwin(window);
+        }
+    }
+}
+
+#[derive(Clone)]
 struct Board {
-    window: WINDOW,
-    next_window: WINDOW,
-    held_window: WINDOW,
-    lines_window: WINDOW,
-    level_window: WINDOW,
-    data: Vec<Square>,
+    field: Playfield,
     player: Player,
     held: Option<Shape>,
-    grab_bag: Vec<Player>,
+    grab_bag: Vec<Shape>,
+    rules: Rules,
+    pieces: Arc<PieceSet>,
+    rng: StdRng,
+    seed: u64,
+    start_level: usize,
     lines: usize,
+    score: usize,
+    locked: usize,
+    dealt: usize,
+    hint: Option<Player>,
+    //Pieces other people are moving on the same field, which are in the way like the stack is
+    others: Vec<Player>,
 }
 
 impl Board {
-    fn new(window: WINDOW, next_window:WINDOW, held_window:WINDOW, lines_window: WINDOW, level_window: WINDOW) -> Board {
-        let mut data: Vec<Square> = Vec::new();
-        for _ in 0..400 {
-            data.push(Square::EMPTY);
-        }
-
-        let mut grab_bag = Board::make_grab_bag();
-        let player = grab_bag.pop().unwrap();
-
-        Board {
-            window,
-            next_window,
-            held_window,
-            lines_window,
-            level_window,
-            data,
-            player,
+    fn new(rules: Rules, pieces: Arc<PieceSet>, start_level: usize, seed: u64) -> Board {
+        let mut board = Board {
+            field: Playfield::new(rules.width, rules.height + rules.buffer),
+            player: Player {
+                shape: Shape(0),
+                orientation: 0,
+                y: 0,
+                x: 0,
+            },
             held: None,
-            grab_bag,
+            grab_bag: Vec::new(),
+            rules,
+            pieces,
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            start_level,
             lines: 0,
-        }
-    }
+            score: 0,
+            locked: 0,
+            dealt: 0,
+            hint: None,
+            others: Vec::new(),
+        };
 
-    fn get(&self, y: usize, x: usize) -> &Square {
-        &self.data[y * 10 + x]
+        board.refill_grab_bag();
+        board.get_new_piece();
+        board
     }
 
-    fn get_mut(&mut self, y: usize, x: usize) -> &mut Square {
-        &mut self.data[y * 10 + x]
+    fn rows(&self) -> usize {
+        self.field.rows()
     }
 
-    fn gravity(&self) -> usize {
-        let level = self.lines / 10;
-        ((0.8 - ((level as f64 - 1.0) * 0.007)).powi(level as i32 - 1) * 100.0) as usize
+    fn in_bounds(&self, y: isize, x: isize) -> bool {
+        0 <= y && y < self.rows() as isize && 0 <= x && x < self.rules.width as isize
     }
 
-    fn draw_score(&self) {
-        mvwprintw(self.lines_window, 1, 1, &format!("{:>3}", self.lines));
-        wrefresh(self.lines_window);
-
-        mvwprintw(self.level_window, 1, 1, &format!("{:>3}", self.lines / 10));
-        wrefresh(self.level_window);
+    fn visible(&self, y: isize, x: isize) -> bool {
+        self.rules.buffer as isize <= y && self.in_bounds(y, x)
     }
 
-    fn draw_next(&mut self){
-        let next = self.grab_bag.pop().unwrap();
+    fn size(&self, player: &Player) -> isize {
+        self.pieces.size(player.shape) as isize
+    }
 
-        let color = match next.shape {
-            Shape::I => COLOR_PAIR(1),
-            Shape::O => COLOR_PAIR(2),
-            Shape::T => COLOR_PAIR(3),
-            Shape::S => COLOR_PAIR(4),
-            Shape::Z => COLOR_PAIR(5),
-            Shape::J => COLOR_PAIR(6),
-            Shape::L => COLOR_PAIR(7),
-        };
+    fn filled(&self, player: &Player, y: isize, x: isize) -> bool {
+        self.pieces.filled(player.shape, player.orientation, y, x)
+    }
 
-        wattron(self.next_window, color);
-        for y in 0..2{
-            wmove(self.next_window, (1 + y) as i32, 1 as i32);
-            for x in 0..4{
-                match next.data[y * 4 + x]{
-                    Square::FULL(_) => {waddch(self.next_window, '@' as chtype);}
-                    Square::EMPTY => {waddch(self.next_window, ' ' as chtype);}
+    fn cells(&self, player: &Player) -> Vec<(isize, isize)> {
+        let mut cells = Vec::new();
+        for y in 0..self.size(player) {
+            for x in 0..self.size(player) {
+                if self.filled(player, y, x) {
+                    cells.push((player.y + y, player.x + x));
                 }
             }
         }
-        wattroff(self.next_window, color);
+        cells
+    }
 
-        wrefresh(self.next_window);
+    fn level(&self) -> usize {
+        match self.rules.set {
+            RuleSet::Modern => self.start_level + self.lines / 10,
+            RuleSet::Classic => {
+                //The first transition waits for the lines the start level would have taken,
+                //then every 10 lines is a level like normal.
+                let first = std::cmp::min(
+                    self.start_level * 10 + 10,
+                    std::cmp::max(100, (self.start_level * 10).saturating_sub(50)),
+                );
+                if self.lines < first {
+                    self.start_level
+                } else {
+                    self.start_level + 1 + (self.lines - first) / 10
+                }
+            }
+        }
+    }
 
-        self.grab_bag.push(next);
+    fn gravity(&self) -> usize {
+        let level = self.level();
+        match self.rules.set {
+            RuleSet::Modern => {
+                ((0.8 - ((level as f64 - 1.0) * 0.007)).powi(level as i32 - 1) * 100.0) as usize
+            }
+            RuleSet::Classic => {
+                //Frames per row on the NES at 60fps, converted to 10ms ticks
+                let frames = match level {
+                    0 => 48,
+                    1 => 43,
+                    2 => 38,
+                    3 => 33,
+                    4 => 28,
+                    5 => 23,
+                    6 => 18,
+                    7 => 13,
+                    8 => 8,
+                    9 => 6,
+                    10..=12 => 5,
+                    13..=15 => 4,
+                    16..=18 => 3,
+                    19..=28 => 2,
+                    _ => 1,
+                };
+                frames * 100 / 60
+            }
+        }
+    }
 
+    fn add_score(&mut self, cleared_lines: usize) {
+        let level = self.level();
+        let points = match self.rules.set {
+            RuleSet::Modern => match cleared_lines {
+                1 => 100,
+                2 => 300,
+                3 => 500,
+                4 => 800,
+                _ => 0,
+            },
+            RuleSet::Classic => match cleared_lines {
+                1 => 40,
+                2 => 100,
+                3 => 300,
+                4 => 1200,
+                _ => 0,
+            },
+        };
+        self.score += points * (level + 1);
     }
 
-    fn draw_held(&mut self){
-        let held = Board::make_piece(self.held.unwrap());
+    fn draw_score(&self, windows: &Windows) {
+        mvwprintw(windows.lines, 1, 1, &format!("{:>3}", self.lines));
+        wrefresh(windows.lines);
 
-        let color = match held.shape {
-            Shape::I => COLOR_PAIR(1),
-            Shape::O => COLOR_PAIR(2),
-            Shape::T => COLOR_PAIR(3),
-            Shape::S => COLOR_PAIR(4),
-            Shape::Z => COLOR_PAIR(5),
-            Shape::J => COLOR_PAIR(6),
-            Shape::L => COLOR_PAIR(7),
-        };
+        mvwprintw(windows.level, 1, 1, &format!("{:>3}", self.level()));
+        wrefresh(windows.level);
 
-        wattron(self.held_window, color);
-        for y in 0..2{
-            wmove(self.held_window, (1 + y) as i32, 1 as i32);
-            for x in 0..4{
-                match held.data[y * 4 + x]{
-                    Square::FULL(_) => {waddch(self.held_window, '@' as chtype);}
-                    Square::EMPTY => {waddch(self.held_window, ' ' as chtype);}
+        mvwprintw(windows.score, 1, 1, &format!("{:>7}", self.score));
+        wrefresh(windows.score);
+    }
+
+    fn draw_preview(&self, window: WINDOW, shape: Shape) {
+        //Just the cells the piece uses, centered in the window
+        let (height, width) = self.pieces.preview_size();
+        let (rows, columns) = self.pieces.bounds(shape);
+        let top = (height - rows.len()) / 2;
+        let left = (width - columns.len()) / 2;
+
+        let color = self.pieces.color(shape);
+
+        wattron(window, color);
+        for y in 0..height {
+            wmove(window, (1 + y) as i32, 1);
+            for x in 0..width {
+                let filled = y >= top
+                    && x >= left
+                    && y - top < rows.len()
+                    && x - left < columns.len()
+                    && self.pieces.filled(
+                        shape,
+                        0,
+                        (rows.start + y - top) as isize,
+                        (columns.start + x - left) as isize,
+                    );
+                if filled {
+                    waddch(window, '@' as chtype);
+                } else {
+                    waddch(window, ' ' as chtype);
                 }
             }
         }
-        wattroff(self.held_window, color);
-
-        wrefresh(self.held_window);
-    }
-
-    fn draw(&self) {
-        for y in 20..40 {
-            wmove(self.window, y - 19 as i32, 1 as i32);
-            for x in 0..10 {
-                if let Square::FULL(s) = self.get(y as usize, x as usize) {
-                    let color = match s {
-                        Shape::I => COLOR_PAIR(1),
-                        Shape::O => COLOR_PAIR(2),
-                        Shape::T => COLOR_PAIR(3),
-                        Shape::S => COLOR_PAIR(4),
-                        Shape::Z => COLOR_PAIR(5),
-                        Shape::J => COLOR_PAIR(6),
-                        Shape::L => COLOR_PAIR(7),
-                    };
-                    wattron(self.window, color);
-                    waddch(self.window, '#' as chtype);
-                    wattroff(self.window, color);
+        wattroff(window, color);
+
+        wrefresh(window);
+    }
+
+    fn draw_next(&self, windows: &Windows) {
+        self.draw_preview(windows.next, *self.grab_bag.last().unwrap());
+    }
+
+    fn draw_held(&self, windows: &Windows) {
+        if let Some(held) = self.held {
+            self.draw_preview(windows.held, held);
+        }
+    }
+
+    fn draw(&self, windows: &Windows) {
+        let buffer = self.rules.buffer as isize;
+
+        for y in self.rules.buffer..self.rows() {
+            wmove(windows.board, (y - self.rules.buffer + 1) as i32, 1);
+            for x in 0..self.rules.width {
+                if let Square::FULL(s) = self.field.get(y, x) {
+                    let color = self.pieces.color(s);
+                    wattron(windows.board, color);
+                    waddch(windows.board, self.pieces.glyph(s));
+                    wattroff(windows.board, color);
+                } else if let Square::GARBAGE = self.field.get(y, x) {
+                    wattron(windows.board, COLOR_PAIR(8));
+                    waddch(windows.board, '#' as chtype);
+                    wattroff(windows.board, COLOR_PAIR(8));
                 } else {
-                    waddch(self.window, ' ' as chtype);
+                    waddch(windows.board, ' ' as chtype);
                 };
             }
         }
 
-        let color = match self.player.shape {
-            Shape::I => COLOR_PAIR(1),
-            Shape::O => COLOR_PAIR(2),
-            Shape::T => COLOR_PAIR(3),
-            Shape::S => COLOR_PAIR(4),
-            Shape::Z => COLOR_PAIR(5),
-            Shape::J => COLOR_PAIR(6),
-            Shape::L => COLOR_PAIR(7),
-        };
+        let color = self.pieces.color(self.player.shape);
+        let glyph = self.pieces.glyph(self.player.shape);
+        let size = self.size(&self.player);
+
+        wattron(windows.board, color);
+
+        if let Some(hint) = self.hint {
+            //Where the bot would put the piece, under the ghost and the piece itself
+            wattron(windows.board, A_BOLD());
+            for y in 0..size {
+                for x in 0..size {
+                    if self.visible(y + hint.y, x + hint.x) && self.filled(&hint, y, x) {
+                        mvwaddch(
+                            windows.board,
+                            (y + hint.y - buffer + 1) as i32,
+                            (x + hint.x + 1) as i32,
+                            '+' as chtype,
+                        );
+                    }
+                }
+            }
+            wattroff(windows.board, A_BOLD());
+        }
+
+        if self.rules.ghost {
+            let ghost = self.bottom_out();
+
+            let p_y = ghost.y;
+            let p_x = ghost.x;
+
+            wattron(windows.board, A_STANDOUT());
+            for y in 0..size {
+                for x in 0..size {
+                    if self.visible(y + p_y, x + p_x) && self.filled(&ghost, y, x) {
+                        mvwaddch(
+                            windows.board,
+                            (y + p_y - buffer + 1) as i32,
+                            (x + p_x + 1) as i32,
+                            glyph,
+                        );
+                    }
+                }
+            }
+            wattroff(windows.board, A_STANDOUT());
+        }
 
-        let ghost = self.bottom_out();
-
-        let p_y = ghost.y;
-        let p_x = ghost.x;
-
-        wattron(self.window, color | A_STANDOUT());
-        for y in 0..4 {
-            for x in 0..4 {
-                if 20 <= (y + p_y) && (y + p_y) <= 39 && 0 <= (x + p_x) && (x + p_x) <= 9 {
-                    match ghost.data[(y * 4 + x) as usize] {
-                        Square::FULL(_) => {
-                            mvwaddch(
-                                self.window,
-                                (y + p_y - 20 + 1) as i32,
-                                (x + p_x + 1) as i32,
-                                '#' as chtype,
-                            );
-                        }
-                        _ => {}
+        //Everyone else's pieces in their own colors, which share the one attribute
+        wattroff(windows.board, color);
+        for (i, other) in self.others.iter().enumerate() {
+            let theirs = self.pieces.color(other.shape);
+            wattron(windows.board, theirs);
+            if self.rules.ghost {
+                wattron(windows.board, A_STANDOUT());
+                for (y, x) in self.cells(&self.landing(i)) {
+                    if self.visible(y, x) {
+                        mvwaddch(
+                            windows.board,
+                            (y - buffer + 1) as i32,
+                            (x + 1) as i32,
+                            self.pieces.glyph(other.shape),
+                        );
                     }
                 }
+                wattroff(windows.board, A_STANDOUT());
+            }
+            for (y, x) in self.cells(other) {
+                if self.visible(y, x) {
+                    mvwaddch(
+                        windows.board,
+                        (y - buffer + 1) as i32,
+                        (x + 1) as i32,
+                        self.pieces.glyph(other.shape),
+                    );
+                }
             }
+            wattroff(windows.board, theirs);
         }
-        wattroff(self.window, A_STANDOUT());
+        wattron(windows.board, color);
 
         let p_y = self.player.y;
         let p_x = self.player.x;
 
-        for y in 0..4 {
-            for x in 0..4 {
-                if 20 <= (y + p_y) && (y + p_y) <= 39 && 0 <= (x + p_x) && (x + p_x) <= 9 {
-                    match self.player.data[(y * 4 + x) as usize] {
-                        Square::FULL(_) => {
-                            mvwaddch(
-                                self.window,
-                                (y + p_y - 20 + 1) as i32,
-                                (x + p_x + 1) as i32,
-                                '#' as chtype,
-                            );
-                        }
-                        _ => {}
-                    }
+        for y in 0..size {
+            for x in 0..size {
+                if self.visible(y + p_y, x + p_x) && self.filled(&self.player, y, x) {
+                    mvwaddch(
+                        windows.board,
+                        (y + p_y - buffer + 1) as i32,
+                        (x + p_x + 1) as i32,
+                        glyph,
+                    );
                 }
             }
         }
-        wattroff(self.window, color);
+        wattroff(windows.board, color);
 
-        wrefresh(self.window);
+        wrefresh(windows.board);
     }
 
-    fn make_piece(shape: Shape) -> Player {
-        let mut data = Vec::new();
-        let mut i = 0b1;
+    fn make_grab_bag(pieces: &PieceSet, rng: &mut StdRng) -> Vec<Shape> {
+        let mut bag: Vec<Shape> = pieces.shapes().collect();
 
-        let map = match shape {
-            Shape::I => 0b1111_0000,
-            Shape::O => 0b0110_0110,
-            Shape::T => 0b0111_0010,
-            Shape::S => 0b0011_0110,
-            Shape::Z => 0b0110_0011,
-            Shape::J => 0b0111_0001,
-            Shape::L => 0b0111_0100,
-        };
+        let slice: &mut [Shape] = &mut bag;
+        slice.shuffle(rng);
 
-        for _ in 0..16 {
-            if i & map != 0 {
-                data.push(Square::FULL(shape));
-            } else {
-                data.push(Square::EMPTY);
-            }
-            i <<= 1;
-        }
+        bag
+    }
 
-        Player {
-            data,
-            shape,
-            direction: Direction::N,
-            y: 18,
-            x: 3,
+    fn make_nes_piece(pieces: &PieceSet, rng: &mut StdRng, previous: Option<Shape>) -> Shape {
+        //Roll one more than there are pieces, reroll once on the dummy value or a repeat of
+        //the last piece
+        let count = pieces.len();
+        let roll = rng.gen_range(0, count + 1);
+        if roll < count && Some(Shape(roll)) != previous {
+            Shape(roll)
+        } else {
+            Shape(rng.gen_range(0, count))
         }
     }
 
-    fn make_grab_bag() -> Vec<Player> {
-        let mut bag = Vec::new();
-
-        bag.push(Board::make_piece(Shape::I));
-        bag.push(Board::make_piece(Shape::O));
-        bag.push(Board::make_piece(Shape::T));
-        bag.push(Board::make_piece(Shape::S));
-        bag.push(Board::make_piece(Shape::Z));
-        bag.push(Board::make_piece(Shape::J));
-        bag.push(Board::make_piece(Shape::L));
-
-        let mut rng = thread_rng();
-        let slice: &mut [Player] = &mut bag;
-        slice.shuffle(&mut rng);
+    fn refill_grab_bag(&mut self) {
+        //The next piece is at the end of the grab bag, so new pieces go in the front
+        let mut new_bag = match self.rules.randomizer {
+            Randomizer::Bag => Board::make_grab_bag(&self.pieces, &mut self.rng),
+            Randomizer::Nes => vec![Board::make_nes_piece(
+                &self.pieces,
+                &mut self.rng,
+                self.grab_bag.first().copied(),
+            )],
+        };
+        self.dealt += new_bag.len();
+        new_bag.append(&mut self.grab_bag);
+        self.grab_bag = new_bag;
+    }
 
-        bag
+    fn spawn(&self, shape: Shape) -> Player {
+        //Pieces start centered, with their bottom row just above the visible playfield
+        let (rows, _) = self.pieces.bounds(shape);
+        Player {
+            shape,
+            orientation: 0,
+            y: self.rules.buffer as isize - rows.end as isize,
+            x: (self.rules.width as isize - self.pieces.size(shape) as isize) / 2,
+        }
     }
 
     fn get_new_piece(&mut self) {
-        if self.grab_bag.len() == 1 {
-            let mut new_bag = Board::make_grab_bag();
-            new_bag.append(&mut self.grab_bag);
-            self.grab_bag = new_bag;
+        while self.grab_bag.len() <= 1 {
+            self.refill_grab_bag();
         }
-        self.player = self.grab_bag.pop().unwrap();
+        let next = self.grab_bag.pop().unwrap();
+        self.player = self.spawn(next);
     }
 
 
     fn check_lines(&mut self) -> bool {
         //Returns true if game should continue
-        for y in 0..20 {
-            for x in 0..10 {
-                if let Square::FULL(_) = self.get(y, x) {
-                    return false;
-                }
-            }
+        if self.topped_out() {
+            return false;
         }
 
-        let mut blocks = 0;
-        let mut cleared_lines = 0;
-        for y in (20..40).rev() {
-            for x in 0..10 {
-                if let Square::FULL(_) = self.get(y, x) {
-                    blocks += 1;
-                }
-            }
-            if blocks == 10 {
-                for _ in 0..10 {
-                    self.data.remove(10 * y);
-                }
-                cleared_lines += 1;
-                self.lines += 1;
-                blocks = 0;
-            } else {
-                blocks = 0;
-            }
-        }
+        let cleared_lines = self.field.clear_lines();
 
-        let mut new_data = Vec::new();
-        for _ in 0..cleared_lines * 10 {
-            new_data.push(Square::EMPTY);
-        }
+        self.add_score(cleared_lines);
+        self.lines += cleared_lines;
+
+        true
+    }
+
+    fn topped_out(&self) -> bool {
+        self.field.any_filled(0..self.rules.buffer)
+    }
 
-        new_data.append(&mut self.data);
-        self.data = new_data;
+    fn insert_garbage(&mut self, hole: usize) -> bool {
+        //Pushes a garbage row in from the bottom, shifting the stack up. The player only goes up
+        //with it when the stack would run into the piece. Returns true if game should continue
+        self.field.push_row(hole, Square::GARBAGE);
 
-        self.draw_score();
+        if self.collision(&self.player) {
+            self.player.y -= 1;
+        }
 
-        return true;
+        !self.topped_out() && !self.collision(&self.player)
     }
 
     fn collision(&self, new_player: &Player) -> bool {
-        let p_y = new_player.y;
-        let p_x = new_player.x;
-
-        for y in 0..4 {
-            for x in 0..4 {
-                if 0 <= (y + p_y) && (y + p_y) <= 39 && 0 <= (x + p_x) && (x + p_x) <= 9 {
-                    match (
-                        self.get((y + p_y) as usize, (x + p_x) as usize),
-                        new_player.data[(y * 4 + x) as usize],
-                    ) {
-                        (Square::FULL(_), Square::FULL(_)) => return true,
-                        _ => {}
-                    }
-                } else {
-                    match new_player.data[(y * 4 + x) as usize] {
-                        Square::FULL(_) => return true,
-                        _ => {}
-                    }
-                }
-            }
-        }
-        false
+        let rows = self.pieces.rows(new_player.shape, new_player.orientation);
+        self.field.collides(new_player.y, new_player.x, rows) || self.blocked(new_player)
     }
 
-    fn lock(&mut self) {
-        let p_y = self.player.y;
-        let p_x = self.player.x;
+    fn blocked(&self, new_player: &Player) -> bool {
+        //Whether it runs into someone else's piece
+        self.others.iter().any(|other| {
+            let theirs = self.cells(other);
+            self.cells(new_player).iter().any(|cell| theirs.contains(cell))
+        })
+    }
 
-        for y in 0..4 {
-            for x in 0..4 {
-                if 0 <= (y + p_y) && (y + p_y) <= 39 && 0 <= (x + p_x) && (x + p_x) <= 9 {
-                    if let Square::FULL(_) = self.player.data[(y * 4 + x) as usize] {
-                        *self.get_mut((p_y + y) as usize, (p_x + x) as usize) =
-                            self.player.data[(y * 4 + x) as usize];
-                    }
-                }
-            }
-        }
+    fn lock(&mut self) {
+        self.locked += 1;
+        self.field.place(
+            self.player.y,
+            self.player.x,
+            self.pieces.rows(self.player.shape, self.player.orientation),
+            Square::FULL(self.player.shape),
+        );
     }
 
-    fn move_player(&mut self, direction: Direction) -> LockResult {
+    fn moved(&self, player: &Player, direction: Direction) -> Option<Player> {
         let new_player = match direction {
-            Direction::N => self.player.clone(),
             Direction::E => Player {
-                x: self.player.x + 1,
-                ..self.player.clone()
+                x: player.x + 1,
+                ..*player
             },
             Direction::S => Player {
-                y: self.player.y + 1,
-                ..self.player.clone()
+                y: player.y + 1,
+                ..*player
             },
             Direction::W => Player {
-                x: self.player.x - 1,
-                ..self.player.clone()
+                x: player.x - 1,
+                ..*player
             },
         };
 
         if !self.collision(&new_player) {
-            self.player = new_player;
-            return LockResult::Unlock;
+            Some(new_player)
         } else {
-            if let Direction::S = direction {
-                return LockResult::Lock;
-            }
+            None
+        }
+    }
+
+    fn move_player(&mut self, direction: Direction) -> LockResult {
+        if let Some(p) = self.moved(&self.player, direction) {
+            self.player = p;
+            return LockResult::Unlock;
+        } else if let Direction::S = direction {
+            return LockResult::Lock;
         }
         LockResult::NoChange
     }
 
     fn bottom_out(&self) -> Player {
-        let mut new_player = self.player.clone();
+        self.dropped(&self.player)
+    }
+
+    fn landing(&self, other: usize) -> Player {
+        //Where someone else's piece would land, with the player's and the rest in its way
+        let mut taken = self.cells(&self.player);
+        for (i, player) in self.others.iter().enumerate() {
+            if i != other {
+                taken.extend(self.cells(player));
+            }
+        }
+
+        let mut ghost = self.others[other];
+        loop {
+            let below = Player {
+                y: ghost.y + 1,
+                ..ghost
+            };
+            let rows = self.pieces.rows(below.shape, below.orientation);
+            if self.field.collides(below.y, below.x, rows)
+                || self.cells(&below).iter().any(|cell| taken.contains(cell))
+            {
+                return ghost;
+            }
+            ghost = below;
+        }
+    }
+
+    fn dropped(&self, player: &Player) -> Player {
+        let mut new_player = *player;
         loop {
             if self.collision(&new_player) {
                 new_player = Player {
@@ -425,7 +795,7 @@ impl Board {
                 }
             }
         }
-        return new_player;
+        new_player
     }
 
     fn hard_drop(&mut self) -> LockResult {
@@ -436,40 +806,26 @@ impl Board {
 
     fn super_rotation_system(
         &self,
+        player: &Player,
         rotated_player: Player,
         direction: Direction,
     ) -> Option<Player> {
-        let possible_kicks = match self.player.shape {
-            Shape::O => [(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)],
-            Shape::I => match (self.player.direction, direction) {
-                (Direction::N, Direction::W) => [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
-                (Direction::N, Direction::E) => [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
-                (Direction::E, Direction::W) => [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
-                (Direction::E, Direction::E) => [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
-                (Direction::S, Direction::W) => [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
-                (Direction::S, Direction::E) => [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
-                (Direction::W, Direction::W) => [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
-                (Direction::W, Direction::E) => [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
-                _ => panic!(),
-            },
-            _ => match (self.player.direction, direction) {
-                (Direction::N, Direction::W) => [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-                (Direction::N, Direction::E) => [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
-                (Direction::E, Direction::W) => [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
-                (Direction::E, Direction::E) => [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
-                (Direction::S, Direction::W) => [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
-                (Direction::S, Direction::E) => [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-                (Direction::W, Direction::W) => [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-                (Direction::W, Direction::E) => [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-                _ => panic!(),
-            },
+        let clockwise = match direction {
+            Direction::E => true,
+            Direction::W => false,
+            _ => panic!(),
+        };
+        let possible_kicks = if self.rules.kicks {
+            self.pieces.kicks(player.shape, player.orientation, clockwise)
+        } else {
+            &[(0, 0)]
         };
 
         for (x, y) in possible_kicks.iter() {
             let test = Player {
                 y: rotated_player.y + y,
                 x: rotated_player.x + x,
-                ..rotated_player.clone()
+                ..rotated_player
             };
             if !self.collision(&test) {
                 return Some(test);
@@ -478,82 +834,35 @@ impl Board {
         None
     }
 
-    fn rotate_player(&mut self, direction: Direction) -> LockResult {
-        let mut rotated_data = self.player.data.clone();
-        let rotated_data = match direction {
-            Direction::E => match self.player.shape {
-                Shape::O => rotated_data,
-                Shape::I => {
-                    for y in 0..4 {
-                        for x in 0..4 {
-                            rotated_data[y * 4 + x] = self.player.data[(3 - x) * 4 + y];
-                        }
-                    }
-                    rotated_data
-                }
-                _ => {
-                    for y in 0..3 {
-                        for x in 0..3 {
-                            rotated_data[y * 4 + x] = self.player.data[(2 - x) * 4 + y];
-                        }
-                    }
-                    rotated_data
-                }
-            },
-            Direction::W => match self.player.shape {
-                Shape::O => rotated_data,
-                Shape::I => {
-                    for y in 0..4 {
-                        for x in 0..4 {
-                            rotated_data[y * 4 + x] = self.player.data[x * 4 + (3 - y)];
-                        }
-                    }
-                    rotated_data
-                }
-                _ => {
-                    for y in 0..3 {
-                        for x in 0..3 {
-                            rotated_data[y * 4 + x] = self.player.data[x * 4 + (2 - y)];
-                        }
-                    }
-                    rotated_data
-                }
-            },
-            _ => rotated_data,
-        };
-
-        let new_direction = match (self.player.direction, direction) {
-            (Direction::N, Direction::W) => Direction::W,
-            (Direction::N, Direction::E) => Direction::E,
-            (Direction::E, Direction::W) => Direction::N,
-            (Direction::E, Direction::E) => Direction::S,
-            (Direction::S, Direction::W) => Direction::E,
-            (Direction::S, Direction::E) => Direction::W,
-            (Direction::W, Direction::W) => Direction::S,
-            (Direction::W, Direction::E) => Direction::N,
+    fn rotated(&self, player: &Player, direction: Direction) -> Option<Player> {
+        let count = self.pieces.orientations(player.shape);
+        let orientation = match direction {
+            Direction::E => (player.orientation + 1) % count,
+            Direction::W => (player.orientation + count - 1) % count,
             _ => panic!(),
         };
 
         let rotated_player = Player {
-            data: rotated_data,
-            direction: new_direction,
-            ..self.player.clone()
+            orientation,
+            ..*player
         };
 
-        let rotated_player = self.super_rotation_system(rotated_player, direction);
+        self.super_rotation_system(player, rotated_player, direction)
+    }
 
-        if let Some(p) = rotated_player {
+    fn rotate_player(&mut self, direction: Direction) -> LockResult {
+        if let Some(p) = self.rotated(&self.player, direction) {
             self.player = p;
-            return LockResult::Unlock;
+            LockResult::Unlock
         } else {
-            return LockResult::NoChange;
+            LockResult::NoChange
         }
     }
 
     fn hold(&mut self) {
         match self.held {
             Some(s) => {
-                let new_player = Board::make_piece(s);
+                let new_player = self.spawn(s);
                 self.held = Some(self.player.shape);
                 self.player = new_player;
             }
@@ -571,140 +880,557 @@ enum LockResult {
     NoChange,
 }
 
-fn main() {
-    initscr();
-    cbreak();
-    noecho();
-    keypad(stdscr(), true);
-    nodelay(stdscr(), true);
-    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+struct Options {
+    mode: Mode,
+    rules: Rules,
+    level: usize,
+    seed: Option<u64>,
+    finesse: bool,
+    finesse_training: bool,
+    ai: bool,
+    hint: bool,
+    weights: ai::Weights,
+    pieces: Arc<PieceSet>,
+    tbp: Option<String>,
+    versus: bool,
+    opponent: Option<String>,
+    broadcast: Option<String>,
+    spectate: Option<String>,
+    royale: Option<usize>,
+    target: royale::Targeting,
+    coop: bool,
+}
 
-    start_color();
-    initialize_color();
+impl Options {
+    fn new(mode: Mode, rules: Rules, level: usize) -> Options {
+        Options {
+            mode,
+            rules,
+            level,
+            seed: None,
+            finesse: false,
+            finesse_training: false,
+            ai: false,
+            hint: false,
+            weights: ai::Weights::standard(),
+            pieces: Arc::new(PieceSet::builtin(rules.pieces)),
+            tbp: None,
+            versus: false,
+            opponent: None,
+            broadcast: None,
+            spectate: None,
+            royale: None,
+            target: royale::Targeting::Random,
+            coop: false,
+        }
+    }
 
-    refresh();
+    fn mode_key(&self) -> &'static str {
+        match (self.rules.set, self.mode) {
+            (RuleSet::Modern, Mode::Marathon) => scores::MODES[0],
+            (RuleSet::Modern, Mode::Survival) => scores::MODES[1],
+            (RuleSet::Classic, Mode::Marathon) => scores::MODES[2],
+            (RuleSet::Classic, Mode::Survival) => scores::MODES[3],
+        }
+    }
+}
 
-    let mut max_y = 0;
-    let mut max_x = 0;
+fn parse_args() -> Option<Options> {
+    //No arguments means the player picks from the menu
+    if std::env::args().len() <= 1 {
+        return None;
+    }
 
-    getmaxyx(stdscr(), &mut max_y, &mut max_x);
+    Some(parse_options(std::env::args().skip(1).collect()))
+}
 
-    let y = (max_y - 22) / 2;
-    let x = (max_x - 12) / 2;
+fn parse_options(args: Vec<String>) -> Options {
+    //For the command line, where a bad flag is the end of it
+    match try_options(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let window = newwin(22, 12, y, x);
-    box_(window, 0, 0);
+fn try_options(args: Vec<String>) -> Result<Options, String> {
+    //Flags that came from somewhere else, like a lobby room, can be wrong without it being fatal
+    let mut options = Options::new(Mode::Marathon, Rules::modern(), 0);
+    //The width is only known to have been picked if it's there, co-op has its own otherwise
+    let mut size = (None, options.rules.height, options.rules.buffer);
+    let mut pieces = None;
+
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--survival" => options.mode = Mode::Survival,
+            "--classic" => options.rules = Rules::classic(),
+            "--finesse" => options.finesse = true,
+            "--finesse-training" => options.finesse_training = true,
+            "--ai" => options.ai = true,
+            "--hint" => options.hint = true,
+            "--versus" => options.versus = true,
+            "--coop" => options.coop = true,
+            "--opponent" => {
+                let opponent = args.next().filter(|o| ai::Bot::difficulty(options.weights, o).is_some());
+                options.opponent = Some(opponent.ok_or("--opponent is easy, medium, hard or max")?);
+                options.versus = true;
+            }
+            "--royale" => {
+                //The number of bots can be left off
+                let bots = args.peek().and_then(|bots| bots.parse::<usize>().ok());
+                if bots.is_some() {
+                    args.next();
+                }
+                let bots = bots
+                    .or(Some(royale::BOTS))
+                    .filter(|bots| (1..=royale::MAX_BOTS).contains(bots));
+                options.royale = Some(bots.ok_or("--royale takes a number of bots from 1 to 48")?);
+            }
+            "--target" => {
+                let target = args.next().and_then(|t| royale::Targeting::parse(&t));
+                options.target = target.ok_or("--target is random, attackers or kos")?;
+            }
+            "--broadcast" => {
+                let address = args.next().ok_or("--broadcast takes a port or a socket path")?;
+                options.broadcast = Some(address);
+            }
+            "--spectate" => {
+                let address = args.next().ok_or("--spectate takes an address or a socket path")?;
+                options.spectate = Some(address);
+            }
+            "--tbp" => {
+                options.tbp = Some(args.next().ok_or("--tbp takes the command that starts a bot")?);
+            }
+            "--weights" => {
+                let weights =
+                    args.next().ok_or("--weights takes a file or a list like holes=-0.4")?;
+                options.weights = ai::Weights::load(&weights)
+                    .ok_or_else(|| format!("Could not read weights from {}", weights))?;
+            }
+            "--level" => {
+                options.level = args
+                    .next()
+                    .and_then(|level| level.parse().ok())
+                    .ok_or("--level takes a number")?;
+            }
+            "--width" => {
+                size.0 = Some(
+                    args.next()
+                        .and_then(|width| width.parse().ok())
+                        .filter(|width| *width >= 4 && *width <= playfield::MAX_WIDTH)
+                        .ok_or("--width takes a number from 4 to 32")?,
+                );
+            }
+            "--height" => {
+                size.1 = args
+                    .next()
+                    .and_then(|height| height.parse().ok())
+                    .filter(|height| *height >= 4)
+                    .ok_or("--height takes a number of at least 4")?;
+            }
+            "--buffer" => {
+                size.2 = args
+                    .next()
+                    .and_then(|buffer| buffer.parse().ok())
+                    .filter(|buffer| *buffer >= 4)
+                    .ok_or("--buffer takes a number of at least 4")?;
+            }
+            "--pieces" => {
+                pieces = Some(args.next().ok_or("--pieces takes a set name or a file")?);
+            }
+            "--seed" => {
+                options.seed = Some(
+                    args.next()
+                        .and_then(|seed| seed.parse().ok())
+                        .ok_or("--seed takes a number")?,
+                );
+            }
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
 
-    mvprintw(y - 2, x + 3, "tetris");
-    refresh();
+    //Only a game of your own can be watched, spectators of the others would wait forever
+    if options.broadcast.is_some() && (options.versus || options.coop || options.royale.is_some()) {
+        return Err("--broadcast can't be used with --versus, --coop or --royale".to_string());
+    }
 
-    mvprintw(y + 2, x + 14, "LINES");
-    let lines_window = newwin(3, 5, y + 3, x + 14);
-    box_(lines_window, 0, 0);
-    wrefresh(lines_window);
+    //Sizes are applied last so they stick whichever rules were picked. Co-op needs room for two
+    //unless it was given a width.
+    let width = if options.coop { coop::WIDTH } else { options.rules.width };
+    options.rules.width = size.0.unwrap_or(width);
+    options.rules.height = size.1;
+    options.rules.buffer = size.2;
 
-    mvprintw(y + 7, x + 14, "LEVEL");
-    let level_window = newwin(3, 5, y + 8, x + 14);
-    box_(level_window, 0, 0);
-    wrefresh(level_window);
+    //Same for the piece set, otherwise it's whatever the rules play with
+    let pieces = pieces.unwrap_or_else(|| options.rules.pieces.to_string());
+    let set = PieceSet::load(&pieces).map_err(|e| format!("Could not load pieces from {}", e))?;
+    options.pieces = Arc::new(set);
 
-    mvprintw(y + 1 , x - 8, " NEXT");
-    let next_window = newwin(4, 6, y + 2, x - 8);
-    box_(next_window, 0, 0);
-    wrefresh(next_window);
+    Ok(options)
+}
 
-    mvprintw(y + 8, x - 8, " HELD");
-    let held_window = newwin(4, 6, y + 9, x - 8);
-    box_(held_window, 0, 0);
-    wrefresh(held_window);
+fn shared_rules(args: &[String]) -> Vec<String> {
+    //The flags that set the game up for everyone, without the ones about how this side plays it
+    let mut rules = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ai" | "--hint" | "--finesse" | "--finesse-training" => {}
+            "--weights" | "--tbp" | "--broadcast" | "--spectate" => {
+                args.next();
+            }
+            _ => rules.push(arg.clone()),
+        }
+    }
+    rules
+}
 
+fn menu(records: &[scores::Record]) -> Option<Options> {
+    let items = [
+        "Marathon",
+        "Survival",
+        "Classic",
+        "Classic survival",
+        "High scores",
+        "Quit",
+    ];
+    let mut selected = 0;
+    let mut level = 0;
 
-    let mut board = Board::new(window, next_window, held_window, lines_window, level_window);
+    nodelay(stdscr(), false);
 
-    board.draw();
-    board.draw_next();
+    loop {
+        let mut max_y = 0;
+        let mut max_x = 0;
+        getmaxyx(stdscr(), &mut max_y, &mut max_x);
+
+        let y = (max_y - items.len() as i32 - 6) / 2;
+        let x = (max_x - 20) / 2;
+
+        clear();
+        mvprintw(y, x + 7, "tetris");
+        for (i, item) in items.iter().enumerate() {
+            if i == selected {
+                attron(A_STANDOUT());
+            }
+            mvprintw(y + 2 + i as i32, x, &format!(" {:<18}", item));
+            if i == selected {
+                attroff(A_STANDOUT());
+            }
+        }
+        mvprintw(y + 3 + items.len() as i32, x, &format!(" Level  < {:>2} >", level));
+        refresh();
+
+        match getch() {
+            KEY_UP => selected = (selected + items.len() - 1) % items.len(),
+            KEY_DOWN => selected = (selected + 1) % items.len(),
+            KEY_LEFT => level = if level == 0 { 0 } else { level - 1 },
+            KEY_RIGHT => level = std::cmp::min(level + 1, 29),
+            10 | KEY_ENTER => {
+                let options = match selected {
+                    0 => Options::new(Mode::Marathon, Rules::modern(), level),
+                    1 => Options::new(Mode::Survival, Rules::modern(), level),
+                    2 => Options::new(Mode::Marathon, Rules::classic(), level),
+                    3 => Options::new(Mode::Survival, Rules::classic(), level),
+                    4 => {
+                        high_scores(records, 0, None);
+                        continue;
+                    }
+                    _ => return None,
+                };
+                clear();
+                nodelay(stdscr(), true);
+                return Some(options);
+            }
+            27 | 113 => return None,
+            _ => {}
+        }
+    }
+}
+
+fn high_scores(records: &[scores::Record], mut mode: usize, highlight: Option<&scores::Record>) {
+    nodelay(stdscr(), false);
+
+    loop {
+        clear();
+        refresh();
+        scores::draw_table(records, scores::MODES[mode], highlight);
+
+        match getch() {
+            KEY_LEFT => mode = (mode + scores::MODES.len() - 1) % scores::MODES.len(),
+            KEY_RIGHT => mode = (mode + 1) % scores::MODES.len(),
+            _ => return,
+        }
+    }
+}
+
+//Where the moves come from
+enum Controller {
+    Keys(fn(i32) -> Option<Action>),
+    Bot(ai::Bot),
+    External(tbp::External),
+}
+
+impl Controller {
+    fn action(&mut self, key: i32, game: &Game) -> Option<Action> {
+        //Bots play through the same actions as the keyboard
+        match self {
+            Controller::Keys(map) => map(key),
+            Controller::Bot(bot) => bot.action(&game.board, game.can_hold),
+            Controller::External(external) => external.action(game),
+        }
+    }
+}
+
+fn play(options: &Options, mut controller: Controller, mut broadcast: Option<spectate::Broadcast>) -> Game {
+    let seed = options
+        .seed
+        .unwrap_or_else(|| thread_rng().gen_range(0, 1_000_000));
+
+    let windows = Windows::new(&options.rules, &options.pieces);
+    let mut game = Game::new(options, seed);
 
     let ten_millis = time::Duration::from_millis(10);
-    let mut time = 0;
-    let mut lock_time = 0;
-    let mut lock = false;
-    let mut can_hold = true;
+    let mut hint_for = None;
 
     loop {
-        let mut set_lock = match getch() {
-            48|99 => {
-                if can_hold {
-                    board.hold();
-                    board.draw_held();
-                    board.draw_next();
-                    can_hold = false;
-                    LockResult::Unlock
-                } else {
-                    LockResult::NoChange
-                }
-            }
-            56|32 => {
-                lock_time = 40;
-                board.hard_drop()
-            }
-            52|KEY_LEFT  => board.move_player(Direction::W),
-            54|KEY_RIGHT=> board.move_player(Direction::E),
-            50|KEY_DOWN=> board.move_player(Direction::S),
-            51|55|122=> board.rotate_player(Direction::W),
-            49|53|57|120|KEY_UP => board.rotate_player(Direction::E),
+        let key = getch();
+        match key {
+            115 => game.stats.toggle(windows.stats),
             27 => break,
-            _ => LockResult::NoChange,
-        };
+            _ => {}
+        }
 
-        match set_lock {
-            LockResult::Lock => {
-                lock = true;
-            }
-            LockResult::Unlock => {
-                lock = false;
-                lock_time = 0;
-                //time = 0;
-            }
-            LockResult::NoChange => {}
+        let action = controller.action(key, &game);
+        let end = game.tick(action);
+
+        //Worked out once for each piece, holding brings a new one
+        let board = &mut game.board;
+        if options.hint && hint_for != Some((board.locked, board.player.shape)) {
+            board.hint = ai::best(board, &board.player, &options.weights).map(|(_, target)| target);
+            hint_for = Some((board.locked, board.player.shape));
         }
 
-        if time >= board.gravity() && !lock {
-            time = 0;
-            set_lock = board.move_player(Direction::S);
-            if let LockResult::Lock = set_lock {
-                lock_time = 0;
-                lock = true;
-            }
+        game.board.draw(&windows);
+        game.board.draw_next(&windows);
+        game.board.draw_held(&windows);
+        game.board.draw_score(&windows);
+        game.stats.draw(windows.stats, game.ticks, game.board.lines, &game.board.pieces);
+        game.finesse.draw(windows.message);
+
+        if let Some(broadcast) = broadcast.as_mut() {
+            broadcast.update(&game);
         }
 
-        if lock {
-            lock_time += 1;
-            if lock_time >= 50 {
-                board.lock();
+        if end.is_some() {
+            break;
+        }
+        thread::sleep(ten_millis);
+    }
 
-                let cont = board.check_lines();
-                if cont {
-                    board.get_new_piece();
-                    board.draw_next();
-                    lock = false;
-                    can_hold = true;
-                    lock_time = 0;
-                    time = 0;
-                } else {
-                    break;
+    if let Some(broadcast) = broadcast.as_mut() {
+        broadcast.end(&game);
+    }
+
+    game
+}
+
+fn main() {
+    //Subcommands run straight in the shell or set the terminal up themselves
+    match std::env::args().nth(1).as_deref() {
+        Some("sim") => return sim::run(std::env::args().skip(2).collect()),
+        Some("tune") => return tune::run(std::env::args().skip(2).collect()),
+        Some("tbp") => return tbp::stub(std::env::args().skip(2).collect()),
+        Some("host") => return net::host(std::env::args().skip(2).collect()),
+        Some("join") => return net::join(std::env::args().skip(2).collect()),
+        Some("serve") => return serve::run(std::env::args().skip(2).collect()),
+        Some("lobby") => return lobby::run(std::env::args().skip(2).collect()),
+        _ => {}
+    }
+
+    let args = parse_args();
+
+    if let Some(address) = args.as_ref().and_then(|options| options.spectate.as_ref()) {
+        return spectate::watch(address);
+    }
+
+    //External bots are started before the terminal is taken over so their errors can be seen
+    let external = match args.as_ref().filter(|options| options.tbp.is_some()) {
+        Some(options) => {
+            if !tbp::supported(&options.rules, &options.pieces) {
+                eprintln!("--tbp only works with the standard pieces on a 10 by 40 playfield");
+                std::process::exit(1);
+            }
+            match tbp::External::launch(options.tbp.as_ref().unwrap()) {
+                Ok(external) => Some(external),
+                Err(e) => {
+                    eprintln!("Could not start the bot: {}", e);
+                    std::process::exit(1);
                 }
             }
         }
+        None => None,
+    };
+
+    let broadcast = match args.as_ref().and_then(|options| options.broadcast.as_ref()) {
+        Some(address) => match spectate::Broadcast::new(address, args.as_ref().unwrap()) {
+            Ok(broadcast) => Some(broadcast),
+            Err(e) => {
+                eprintln!("Could not broadcast on {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
 
-        time += 1;
+    start_terminal();
 
-        board.draw();
-        thread::sleep(ten_millis);
+    let mut records = scores::load();
+
+    let options = match args.or_else(|| menu(&records)) {
+        Some(options) => options,
+        None => {
+            endwin();
+            return;
+        }
+    };
+
+    if let Some(bots) = options.royale {
+        let outcome = royale::play(&options, bots);
+        endwin();
+
+        let lines = outcome.game.board.lines;
+        match outcome.place {
+            Some(1) => println!(
+                "You won the battle royale against {} bots!! {} KOs and {} lines!",
+                outcome.players - 1,
+                outcome.kos,
+                lines
+            ),
+            Some(place) => println!(
+                "You came {} of {} with {} KOs and {} lines.",
+                ordinal(place),
+                outcome.players,
+                outcome.kos,
+                lines
+            ),
+            None => println!("You quit with {} KOs and {} lines.", outcome.kos, lines),
+        }
+        return;
+    }
+
+    if options.coop {
+        let coop = coop::play(&options);
+        endwin();
+
+        let board = &coop.board;
+        println!("You cleared {} lines together for {} points!", board.lines, board.score);
+        return;
+    }
+
+    if options.versus {
+        let outcome = versus::play(&options);
+        endwin();
+
+        let lines: Vec<usize> = outcome.games.iter().map(|game| game.board.lines).collect();
+        match outcome.winner {
+            Some(0) if options.opponent.is_some() => {
+                println!("You beat the bot!! {} lines to {}!", lines[0], lines[1])
+            }
+            Some(_) if options.opponent.is_some() => {
+                println!("The bot beat you!! {} lines to {}!", lines[1], lines[0])
+            }
+            Some(winner) => println!(
+                "Player {} wins!! {} lines to {}!",
+                winner + 1,
+                lines[winner],
+                lines[1 - winner]
+            ),
+            None => println!("Nobody won, it was {} lines to {}.", lines[0], lines[1]),
+        }
+        return;
+    }
+
+    let controller = match external {
+        Some(external) => Controller::External(external),
+        None if options.ai => Controller::Bot(ai::Bot::new(options.weights)),
+        None => Controller::Keys(Action::from_key),
+    };
+    let game = play(&options, controller, broadcast);
+    let (board, ticks) = (&game.board, game.ticks);
+
+    let mut record = scores::Record::new(
+        options.mode_key(),
+        board.score,
+        board.lines,
+        board.level(),
+        ticks,
+        board.seed,
+    );
+
+    let mut saved = Ok(());
+    //Odd sized playfields, piece sets, hinted games and bots' games aren't comparable, so
+    //they stay out of the tables
+    let comparable = !options.ai
+        && options.tbp.is_none()
+        && !options.hint
+        && options.rules.standard_size()
+        && options.pieces.name == options.rules.pieces;
+    if comparable && scores::is_record(&records, &record) {
+        flushinp();
+        record.name = scores::prompt_name();
+        scores::insert(&mut records, record.clone());
+        saved = scores::save(&records);
+
+        let mode = scores::MODES.iter().position(|m| *m == record.mode).unwrap();
+        high_scores(&records, mode, Some(&record));
     }
 
     endwin();
 
-    println!("You lost at tetris!! You got {} lines!", board.lines);
+    if let Err(e) = saved {
+        eprintln!("Could not save high scores: {}", e);
+    }
+
+    match options.mode {
+        Mode::Marathon => println!(
+            "You lost at tetris!! You got {} lines and {} points!",
+            board.lines, board.score
+        ),
+        Mode::Survival => println!(
+            "You survived {} seconds of garbage!! You got {} lines!",
+            ticks / 100,
+            board.lines
+        ),
+    }
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+fn start_terminal() {
+    initscr();
+    cbreak();
+    noecho();
+    keypad(stdscr(), true);
+    nodelay(stdscr(), true);
+    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+
+    start_color();
+    initialize_color();
+
+    refresh();
 }
 
 fn initialize_color() {
@@ -715,4 +1441,5 @@ fn initialize_color() {
     init_pair(5, COLOR_RED, COLOR_BLACK);
     init_pair(6, COLOR_BLUE, COLOR_BLACK);
     init_pair(7, COLOR_WHITE, COLOR_BLACK);
+    init_pair(8, COLOR_BLACK, COLOR_WHITE);
 }
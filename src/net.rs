@@ -0,0 +1,578 @@
+use crate::ai::Bot;
+use crate::attack::Attack;
+use crate::finesse::Finesse;
+use crate::game::Game;
+use crate::pieces::Shape;
+use crate::rollback::{self, Session};
+use crate::playfield::Playfield;
+use crate::{
+    parse_options, shared_rules, start_terminal, Action, Board, Controller, Options, Player, Square,
+    Windows,
+};
+use ncurses::*;
+use rand::{thread_rng, Rng};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{self, Duration, Instant};
+
+pub const PORT: u16 = 7777;
+
+//Ticks between the board pictures each side sends the other
+const SNAPSHOT_TICKS: usize = 5;
+
+//A line to send and when it's due to go
+type Delayed = (Instant, String);
+
+//One JSON message per line each way, read on a thread of its own so the game never waits
+pub struct Peer {
+    stream: TcpStream,
+    messages: Receiver<Value>,
+    //Messages held back to pretend the other side is further away, and what sends them
+    delayed: Option<(Sender<Delayed>, JoinHandle<()>)>,
+    latency: Duration,
+}
+
+impl Peer {
+    pub fn new(stream: TcpStream) -> Result<Peer, String> {
+        stream.set_nodelay(true).map_err(|e| e.to_string())?;
+        let reader = stream.try_clone().map_err(|e| e.to_string())?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let message = match line.ok().and_then(|l| serde_json::from_str(&l).ok()) {
+                    Some(message) => message,
+                    None => break,
+                };
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Peer {
+            stream,
+            messages,
+            delayed: None,
+            latency: Duration::ZERO,
+        })
+    }
+
+    pub fn delay(&mut self, latency: Duration) -> Result<(), String> {
+        //Everything sent from now on takes this much longer to get there
+        let mut writer = self.stream.try_clone().map_err(|e| e.to_string())?;
+        let (sender, delayed) = mpsc::channel::<Delayed>();
+        let thread = thread::spawn(move || {
+            for (due, line) in delayed {
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+                if writeln!(writer, "{}", line).is_err() {
+                    break;
+                }
+            }
+        });
+        self.delayed = Some((sender, thread));
+        self.latency = latency;
+        Ok(())
+    }
+
+    pub fn send(&mut self, message: &Value) -> bool {
+        match &self.delayed {
+            Some((sender, _)) => {
+                let due = Instant::now() + self.latency;
+                sender.send((due, message.to_string())).is_ok()
+            }
+            None => writeln!(self.stream, "{}", message).is_ok(),
+        }
+    }
+
+    pub fn flush(&mut self) {
+        //Waits for anything still being held back to go out
+        if let Some((sender, thread)) = self.delayed.take() {
+            drop(sender);
+            let _ = thread.join();
+        }
+    }
+
+    pub fn recv(&self) -> Option<Value> {
+        self.messages.recv().ok()
+    }
+
+    pub fn try_recv(&self) -> Result<Value, TryRecvError> {
+        self.messages.try_recv()
+    }
+
+    pub fn recv_timeout(&self, timeout: time::Duration) -> Result<Value, RecvTimeoutError> {
+        self.messages.recv_timeout(timeout)
+    }
+}
+
+fn cell(square: Square) -> char {
+    match square {
+        Square::EMPTY => '.',
+        Square::GARBAGE => 'G',
+        Square::FULL(shape) => (b'a' + shape.0 as u8) as char,
+    }
+}
+
+fn square(cell: char) -> Square {
+    match cell {
+        '.' => Square::EMPTY,
+        'G' => Square::GARBAGE,
+        c => Square::FULL(Shape((c as u8).wrapping_sub(b'a') as usize)),
+    }
+}
+
+pub fn snapshot(game: &Game) -> Value {
+    //Everything the other side needs to draw this board
+    let board = &game.board;
+    let field: Vec<String> = (0..board.rows())
+        .map(|y| (0..board.rules.width).map(|x| cell(board.field.get(y, x))).collect())
+        .collect();
+    let player = &board.player;
+
+    json!({
+        "type": "board",
+        "field": field,
+        "player": [player.shape.0, player.orientation, player.y, player.x],
+        "held": board.held.map(|s| s.0),
+        "queue": board.grab_bag.iter().rev().map(|s| s.0).collect::<Vec<_>>(),
+        "lines": board.lines,
+        "score": board.score,
+        "pending": game.attack.pending(),
+        "ticks": game.ticks,
+        "pieces": game.stats.pieces,
+        "keys": game.stats.keys,
+        "faults": game.stats.faults,
+        "counts": game.stats.counts,
+    })
+}
+
+pub fn apply(board: &mut Board, attack: &mut Attack, message: &Value) -> Option<()> {
+    //Copies a snapshot onto a board that's only there to be drawn
+    let count = board.pieces.len();
+    let shape = |value: &Value| -> Option<Shape> {
+        let shape = value.as_u64()? as usize;
+        if shape < count {
+            Some(Shape(shape))
+        } else {
+            None
+        }
+    };
+
+    let player = message["player"].as_array()?;
+    board.player = Player {
+        shape: shape(player.first()?)?,
+        orientation: player.get(1)?.as_u64()? as usize,
+        y: player.get(2)?.as_i64()? as isize,
+        x: player.get(3)?.as_i64()? as isize,
+    };
+    if board.player.orientation >= board.pieces.orientations(board.player.shape) {
+        return None;
+    }
+    board.held = shape(&message["held"]);
+    let queue = message["queue"].as_array()?;
+    board.grab_bag = queue.iter().rev().map(shape).collect::<Option<_>>()?;
+    if board.grab_bag.is_empty() {
+        return None;
+    }
+    board.lines = message["lines"].as_u64()? as usize;
+    board.score = message["score"].as_u64()? as usize;
+
+    let field = message["field"].as_array()?;
+    let mut rows = Vec::new();
+    for row in field.iter().take(board.rows()) {
+        let row: Vec<Square> = row.as_str()?.chars().take(board.rules.width).map(square).collect();
+        rows.push(row);
+    }
+    board.field = Playfield::new(board.rules.width, board.rows());
+    for (y, row) in rows.iter().enumerate() {
+        for (x, square) in row.iter().enumerate() {
+            match square {
+                Square::FULL(s) if s.0 >= count => return None,
+                Square::EMPTY => {}
+                _ => board.field.place(y as isize, x as isize, &[1], *square),
+            }
+        }
+    }
+
+    *attack = Attack::new();
+    attack.receive(message["pending"].as_u64()? as usize);
+    Some(())
+}
+
+//How a game over the network ended, from this side
+enum Ending {
+    Won,
+    Lost,
+    Draw,
+    Quit,
+    Forfeit,
+    Disconnected,
+}
+
+fn play(options: &Options, peer: &mut Peer, seed: u64) -> (Ending, Game) {
+    let windows = [
+        Windows::at(&options.rules, &options.pieces, COLS() / 4, "YOU"),
+        Windows::at(&options.rules, &options.pieces, COLS() * 3 / 4, "THEM"),
+    ];
+    let mut game = Game::new(options, seed);
+    let mut them = Board::new(options.rules, options.pieces.clone(), options.level, seed);
+    let mut their_attack = Attack::new();
+
+    let mut controller = if options.ai {
+        Controller::Bot(Bot::new(options.weights))
+    } else {
+        Controller::Keys(Action::from_key)
+    };
+
+    let ten_millis = time::Duration::from_millis(10);
+
+    let result = loop {
+        let key = getch();
+        if key == 27 {
+            peer.send(&json!({"type": "quit"}));
+            break Ending::Quit;
+        }
+
+        let action = controller.action(key, &game);
+        if game.tick(action).is_some() {
+            peer.send(&json!({"type": "lost"}));
+            break Ending::Lost;
+        }
+
+        let sent = game.attack.take_outgoing();
+        if sent > 0 {
+            peer.send(&json!({"type": "attack", "rows": sent}));
+        }
+        if game.ticks.is_multiple_of(SNAPSHOT_TICKS) {
+            peer.send(&snapshot(&game));
+        }
+
+        //Whatever came in since the last tick
+        let mut over = None;
+        loop {
+            match peer.try_recv() {
+                Ok(message) => match message["type"].as_str() {
+                    Some("attack") => {
+                        game.attack.receive(message["rows"].as_u64().unwrap_or(0) as usize);
+                    }
+                    Some("board") => {
+                        apply(&mut them, &mut their_attack, &message);
+                    }
+                    Some("lost") => over = Some(Ending::Won),
+                    Some("quit") => over = Some(Ending::Forfeit),
+                    _ => {}
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    over.get_or_insert(Ending::Disconnected);
+                    break;
+                }
+            }
+        }
+        if let Some(result) = over {
+            break result;
+        }
+
+        draw(&windows[0], &game.board, &game.attack);
+        game.finesse.draw(windows[0].message);
+        draw(&windows[1], &them, &their_attack);
+
+        thread::sleep(ten_millis);
+    };
+
+    //Both can top out before hearing about the other, that's a draw. The winner just hangs up.
+    let result = match result {
+        Ending::Lost => loop {
+            match peer.recv_timeout(time::Duration::from_secs(2)) {
+                Ok(message) if message["type"] == "lost" => break Ending::Draw,
+                Ok(_) => {}
+                Err(_) => break Ending::Lost,
+            }
+        },
+        result => result,
+    };
+    (result, game)
+}
+
+pub fn draw(windows: &Windows, board: &Board, attack: &Attack) {
+    board.draw(windows);
+    board.draw_next(windows);
+    board.draw_held(windows);
+    board.draw_score(windows);
+    attack.draw(windows.meter);
+}
+
+fn play_rollback(
+    options: &Options,
+    peer: &mut Peer,
+    seed: u64,
+    local: usize,
+    training: [bool; 2],
+) -> (Ending, Session) {
+    //Only inputs go over the wire, both boards are played here
+    let windows = [
+        Windows::at(&options.rules, &options.pieces, COLS() / 4, "YOU"),
+        Windows::at(&options.rules, &options.pieces, COLS() * 3 / 4, "THEM"),
+    ];
+    //Finesse training sends pieces back up, so each board has to play by its own side's setting
+    let game = |training| {
+        let mut game = Game::new(options, seed);
+        game.finesse = Finesse::new(options.finesse, training);
+        game
+    };
+    let mut session = Session::new([game(training[0]), game(training[1])], local);
+
+    let mut controller = if options.ai {
+        Controller::Bot(Bot::new(options.weights))
+    } else {
+        Controller::Keys(Action::from_key)
+    };
+
+    let ten_millis = time::Duration::from_millis(10);
+
+    let result = loop {
+        let key = getch();
+        if key == 27 {
+            peer.send(&json!({"type": "quit"}));
+            break Ending::Quit;
+        }
+
+        //Too far ahead of them means waiting, keys pressed meanwhile are lost
+        let mut hashes = Vec::new();
+        if session.can_advance() {
+            let action = controller.action(key, &session.games[local]);
+            let frame = session.frame;
+            hashes = session.advance(action);
+            peer.send(&json!({"type": "input", "frame": frame, "action": rollback::encode(action)}));
+        }
+
+        let mut over = None;
+        loop {
+            match peer.try_recv() {
+                Ok(message) => match message["type"].as_str() {
+                    Some("input") => {
+                        let frame = message["frame"].as_u64().unwrap_or(0) as usize;
+                        let action = rollback::decode(message["action"].as_u64().unwrap_or(0) as usize);
+                        hashes.extend(session.remote(frame, action));
+                    }
+                    Some("hash") => {
+                        let frame = message["frame"].as_u64().unwrap_or(0) as usize;
+                        session.their_hash(frame, message["hash"].as_u64().unwrap_or(0));
+                    }
+                    Some("quit") => over = Some(Ending::Forfeit),
+                    _ => {}
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    over.get_or_insert(Ending::Disconnected);
+                    break;
+                }
+            }
+        }
+        for (frame, hash) in hashes {
+            peer.send(&json!({"type": "hash", "frame": frame, "hash": hash}));
+        }
+
+        //Both sides play out the same frames, so they agree on who lost without asking
+        match session.loser {
+            Some(loser) if loser == local => break Ending::Lost,
+            Some(_) => break Ending::Won,
+            None => {}
+        }
+        if let Some(result) = over {
+            break result;
+        }
+
+        let (ours, theirs) = (&session.games[local], &session.games[1 - local]);
+        draw(&windows[0], &ours.board, &ours.attack);
+        draw(&windows[1], &theirs.board, &theirs.attack);
+        if session.desync.is_some() {
+            mvwprintw(windows[1].message, 0, 1, " DESYNC");
+            wrefresh(windows[1].message);
+        }
+
+        thread::sleep(ten_millis);
+    };
+
+    //Our last inputs still have to get to them so they can finish the same way
+    peer.flush();
+    (result, session)
+}
+
+fn finish(result: Ending, game: &Game) {
+    endwin();
+    let lines = game.board.lines;
+    match result {
+        Ending::Won => println!("You won!! You got {} lines!", lines),
+        Ending::Lost => println!("You lost!! You got {} lines.", lines),
+        Ending::Draw => println!("You both topped out at once, it's a draw with {} lines.", lines),
+        Ending::Quit => println!("You quit with {} lines.", lines),
+        Ending::Forfeit => println!("They quit, so you win with {} lines!", lines),
+        Ending::Disconnected => println!("Lost the connection with {} lines.", lines),
+    }
+}
+
+fn run(
+    options: &Options,
+    peer: &mut Peer,
+    seed: u64,
+    rollback: bool,
+    local: usize,
+    training: [bool; 2],
+) {
+    start_terminal();
+    if rollback {
+        let (result, session) = play_rollback(options, peer, seed, local, training);
+        finish(result, &session.games[local]);
+        println!("Rolled back {} times.", session.rollbacks);
+        if let Some(frame) = session.desync {
+            println!("The two games stopped matching by frame {}!", frame);
+        }
+    } else {
+        let (result, game) = play(options, peer, seed);
+        peer.flush();
+        finish(result, &game);
+    }
+}
+
+fn latency(args: &mut impl Iterator<Item = String>) -> Duration {
+    Duration::from_millis(
+        args.next()
+            .and_then(|ms| ms.parse().ok())
+            .expect("--latency takes a number of milliseconds"),
+    )
+}
+
+pub fn host(args: Vec<String>) {
+    //Game flags are passed on so both sides play by the same rules
+    let mut port = PORT;
+    let mut rollback = false;
+    let mut delay = Duration::ZERO;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .and_then(|port| port.parse().ok())
+                    .expect("--port takes a number");
+            }
+            "--rollback" => rollback = true,
+            "--latency" => delay = latency(&mut args),
+            _ => rest.push(arg),
+        }
+    }
+
+    let mut options = parse_options(rest.clone());
+    let seed = options
+        .seed
+        .unwrap_or_else(|| thread_rng().gen_range(0, 1_000_000));
+    options.seed = Some(seed);
+
+    let listener = match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on port {}: {}", port, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Waiting for someone to join on port {}", port);
+
+    let accepted = listener.accept().map_err(|e| e.to_string());
+    let mut peer = match accepted.and_then(|(stream, _)| Peer::new(stream)) {
+        Ok(peer) => peer,
+        Err(e) => {
+            eprintln!("Could not connect: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let start = json!({
+        "type": "start",
+        "seed": seed,
+        //Only the rules, a weights file or bot the host has could keep them from starting
+        "args": shared_rules(&rest),
+        "rollback": rollback,
+        "training": options.finesse_training
+    });
+    peer.send(&start);
+    let ready = match peer.recv().filter(|m| m["type"] == "ready") {
+        Some(ready) => ready,
+        None => {
+            eprintln!("They left before the game started");
+            std::process::exit(1);
+        }
+    };
+    let training = [options.finesse_training, ready["training"] == true];
+
+    if !delay.is_zero() {
+        peer.delay(delay).unwrap_or_else(|e| eprintln!("Could not slow the connection down: {}", e));
+    }
+    run(&options, &mut peer, seed, rollback, 0, training);
+}
+
+pub fn join(args: Vec<String>) {
+    let mut delay = Duration::ZERO;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--latency" => delay = latency(&mut args),
+            _ => rest.push(arg),
+        }
+    }
+    let args = rest;
+
+    let address = match args.first() {
+        Some(address) if address.contains(':') => address.clone(),
+        Some(address) => format!("{}:{}", address, PORT),
+        None => {
+            eprintln!("join takes the address of the host, like localhost or 192.168.1.5:7777");
+            std::process::exit(1);
+        }
+    };
+
+    let mut peer = match TcpStream::connect(&address)
+        .map_err(|e| e.to_string())
+        .and_then(Peer::new)
+    {
+        Ok(peer) => peer,
+        Err(e) => {
+            eprintln!("Could not connect to {}: {}", address, e);
+            std::process::exit(1);
+        }
+    };
+
+    //The host picks the rules and the seed
+    let start = match peer.recv().filter(|m| m["type"] == "start") {
+        Some(start) => start,
+        None => {
+            eprintln!("{} didn't start a game", address);
+            std::process::exit(1);
+        }
+    };
+    let rules: Vec<String> = start["args"]
+        .as_array()
+        .map(|args| args.iter().filter_map(|a| a.as_str().map(String::from)).collect())
+        .unwrap_or_default();
+    let seed = start["seed"].as_u64().unwrap_or(0);
+    let mut options = parse_options(rules);
+    options.seed = Some(seed);
+
+    //Who's playing this side is still up to whoever joined
+    let mine = parse_options(args[1..].to_vec());
+    options.ai = mine.ai;
+    options.weights = mine.weights;
+    options.finesse = mine.finesse;
+    options.finesse_training = mine.finesse_training;
+    peer.send(&json!({"type": "ready", "training": options.finesse_training}));
+
+    if !delay.is_zero() {
+        peer.delay(delay).unwrap_or_else(|e| eprintln!("Could not slow the connection down: {}", e));
+    }
+    let rollback = start["rollback"].as_bool().unwrap_or(false);
+    let training = [start["training"] == true, options.finesse_training];
+    run(&options, &mut peer, seed, rollback, 1, training);
+}
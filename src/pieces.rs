@@ -0,0 +1,342 @@
+use ncurses::*;
+use std::fs;
+
+//The biggest grid a piece can be drawn in
+pub const MAX_SIZE: usize = 8;
+
+pub const BUILTIN: [(&str, &str); 6] = [
+    ("standard", include_str!("pieces/standard.txt")),
+    ("nes", include_str!("pieces/nes.txt")),
+    ("pentomino", include_str!("pieces/pentomino.txt")),
+    ("big", include_str!("pieces/big.txt")),
+    ("sz", include_str!("pieces/sz.txt")),
+    ("mono", include_str!("pieces/mono.txt")),
+];
+
+//Wall kicks out of each orientation, indexed by [orientation][counterclockwise, clockwise]
+type KickTable = [[[(isize, isize); 5]; 2]; 4];
+
+const SRS: KickTable = [
+    [[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)], [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)]],
+    [[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)], [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)]],
+    [[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)]],
+    [[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)], [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)]],
+];
+
+const SRS_I: KickTable = [
+    [[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)], [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)]],
+    [[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)], [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)]],
+    [[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)], [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)]],
+    [[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)], [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)]],
+];
+
+//The offsets tried in order when a piece rotates
+type Kicks = Vec<(isize, isize)>;
+
+const COLORS: [&str; 7] = ["cyan", "yellow", "magenta", "green", "red", "blue", "white"];
+
+//An index into the loaded piece set
+#[derive(Copy, Clone, PartialEq)]
+pub struct Shape(pub usize);
+
+struct Piece {
+    name: String,
+    color: i16,
+    glyph: char,
+    size: usize,
+    //Every orientation as a row mask per grid row, with bit x set for column x
+    orientations: Vec<Vec<u64>>,
+    //Offsets to try when rotating out of each orientation, counterclockwise then clockwise
+    kicks: Vec<[Kicks; 2]>,
+}
+
+pub struct PieceSet {
+    pub name: String,
+    pieces: Vec<Piece>,
+}
+
+//Everything a piece definition can say, before it is checked and turned into a Piece
+#[derive(Default)]
+struct Definition {
+    name: String,
+    color: i16,
+    glyph: char,
+    rotations: Option<usize>,
+    table: Option<&'static KickTable>,
+    kicks: Vec<(usize, usize, Kicks)>,
+    grids: Vec<Vec<Vec<bool>>>,
+}
+
+fn rotate(grid: &[Vec<bool>]) -> Vec<Vec<bool>> {
+    //Clockwise, around the middle of the grid
+    let size = grid.len();
+    (0..size)
+        .map(|y| (0..size).map(|x| grid[size - 1 - x][y]).collect())
+        .collect()
+}
+
+fn parse_offset(word: &str) -> Option<(isize, isize)> {
+    let (x, y) = word.split_once(',')?;
+    Some((x.parse().ok()?, y.parse().ok()?))
+}
+
+impl Definition {
+    fn build(self) -> Result<Piece, String> {
+        let size = match self.grids.first() {
+            Some(grid) => grid.len(),
+            None => return Err(format!("piece {} has no cells", self.name)),
+        };
+        if size > MAX_SIZE {
+            return Err(format!("piece {} is bigger than {}x{}", self.name, MAX_SIZE, MAX_SIZE));
+        }
+        for grid in self.grids.iter() {
+            if grid.len() != size || grid.iter().any(|row| row.len() != size) {
+                return Err(format!("piece {} needs square grids of the same size", self.name));
+            }
+            if !grid.iter().flatten().any(|cell| *cell) {
+                return Err(format!("piece {} has an empty orientation", self.name));
+            }
+        }
+
+        //A single grid is turned to get the rest, 4 times unless it says otherwise
+        let grids = if self.grids.len() == 1 {
+            let mut grids = self.grids;
+            for _ in 1..self.rotations.unwrap_or(4) {
+                let turned = rotate(grids.last().unwrap());
+                grids.push(turned);
+            }
+            grids
+        } else {
+            self.grids
+        };
+
+        let orientations: Vec<Vec<u64>> = grids
+            .iter()
+            .map(|grid| {
+                grid.iter()
+                    .map(|row| {
+                        row.iter()
+                            .enumerate()
+                            .filter(|(_, cell)| **cell)
+                            .fold(0, |mask, (x, _)| mask | 1 << x)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut kicks = vec![[vec![(0, 0)], vec![(0, 0)]]; orientations.len()];
+        if let Some(table) = self.table {
+            if orientations.len() != 4 {
+                return Err(format!("piece {} needs 4 orientations for SRS kicks", self.name));
+            }
+            for (from, tests) in table.iter().enumerate() {
+                kicks[from] = [tests[0].to_vec(), tests[1].to_vec()];
+            }
+        }
+        for (from, clockwise, tests) in self.kicks {
+            if from >= orientations.len() {
+                return Err(format!("piece {} has no orientation {}", self.name, from));
+            }
+            kicks[from][clockwise] = tests;
+        }
+
+        Ok(Piece {
+            name: self.name,
+            color: self.color,
+            glyph: self.glyph,
+            size,
+            orientations,
+            kicks,
+        })
+    }
+}
+
+impl PieceSet {
+    pub fn builtin(name: &str) -> PieceSet {
+        let (_, text) = BUILTIN.iter().find(|(n, _)| *n == name).unwrap();
+        PieceSet::parse(name, text).unwrap()
+    }
+
+    pub fn load(name: &str) -> Result<PieceSet, String> {
+        //Either one of the sets that come with the game or the path to a file
+        if BUILTIN.iter().any(|(n, _)| *n == name) {
+            return Ok(PieceSet::builtin(name));
+        }
+
+        let text = fs::read_to_string(name).map_err(|e| format!("{}: {}", name, e))?;
+        PieceSet::parse(name, &text).map_err(|e| format!("{}: {}", name, e))
+    }
+
+    pub fn parse(name: &str, text: &str) -> Result<PieceSet, String> {
+        let mut definitions: Vec<Definition> = Vec::new();
+        let mut grid: Vec<Vec<bool>> = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+
+            //A grid ends at anything that isn't a grid row
+            if !line.is_empty() && line.chars().all(|c| c == '.' || c == '#') {
+                grid.push(line.chars().map(|c| c == '#').collect());
+                continue;
+            }
+            if !grid.is_empty() {
+                match definitions.last_mut() {
+                    Some(definition) => definition.grids.push(std::mem::take(&mut grid)),
+                    None => return Err(error("cells before any piece")),
+                }
+            }
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words[0] == "piece" {
+                if words.len() < 3 || words.len() > 4 {
+                    return Err(error("expected piece <name> <color> [glyph]"));
+                }
+                let color = match COLORS.iter().position(|c| *c == words[2]) {
+                    Some(i) => i as i16 + 1,
+                    None => return Err(error(&format!("unknown color {}", words[2]))),
+                };
+                //Anything past ASCII would spill into the attribute bits of a chtype
+                let glyph = words.get(3).and_then(|g| g.chars().next()).unwrap_or('#');
+                if !glyph.is_ascii_graphic() {
+                    return Err(error(&format!("glyph {} isn't a printable ASCII character", glyph)));
+                }
+                definitions.push(Definition {
+                    name: words[1].to_string(),
+                    color,
+                    glyph,
+                    ..Definition::default()
+                });
+                continue;
+            }
+
+            let definition = match definitions.last_mut() {
+                Some(definition) => definition,
+                None => return Err(error("expected a piece first")),
+            };
+            match (words[0], words.len()) {
+                ("rotations", 2) => {
+                    definition.rotations = match words[1].parse() {
+                        Ok(n) if (1..=4).contains(&n) => Some(n),
+                        _ => return Err(error("rotations takes a number from 1 to 4")),
+                    };
+                }
+                ("kicks", 2) => {
+                    definition.table = match words[1] {
+                        "srs" => Some(&SRS),
+                        "srs-i" => Some(&SRS_I),
+                        "none" => None,
+                        _ => return Err(error("kicks is one of srs, srs-i or none")),
+                    };
+                }
+                ("kick", n) if n >= 3 => {
+                    let from = words[1].parse().map_err(|_| error("bad orientation"))?;
+                    let clockwise = match words[2] {
+                        "ccw" => 0,
+                        "cw" => 1,
+                        _ => return Err(error("kick direction is cw or ccw")),
+                    };
+                    let tests = words[3..]
+                        .iter()
+                        .map(|word| parse_offset(word))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| error("kick offsets look like x,y"))?;
+                    definition.kicks.push((from, clockwise, tests));
+                }
+                _ => return Err(error(&format!("don't know what to do with {}", line))),
+            }
+        }
+        if let Some(definition) = definitions.last_mut() {
+            if !grid.is_empty() {
+                definition.grids.push(grid);
+            }
+        }
+
+        if definitions.is_empty() {
+            return Err("no pieces".to_string());
+        }
+        let pieces = definitions
+            .into_iter()
+            .map(Definition::build)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(PieceSet {
+            name: name.to_string(),
+            pieces,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pieces.len()
+    }
+
+    pub fn shapes(&self) -> impl Iterator<Item = Shape> {
+        (0..self.pieces.len()).map(Shape)
+    }
+
+    pub fn find(&self, name: &str) -> Option<Shape> {
+        self.pieces
+            .iter()
+            .position(|piece| piece.name == name)
+            .map(Shape)
+    }
+
+    pub fn name(&self, shape: Shape) -> &str {
+        &self.pieces[shape.0].name
+    }
+
+    pub fn color(&self, shape: Shape) -> attr_t {
+        COLOR_PAIR(self.pieces[shape.0].color)
+    }
+
+    pub fn pair(&self, shape: Shape) -> i16 {
+        self.pieces[shape.0].color
+    }
+
+    pub fn glyph(&self, shape: Shape) -> chtype {
+        self.pieces[shape.0].glyph as chtype
+    }
+
+    pub fn size(&self, shape: Shape) -> usize {
+        self.pieces[shape.0].size
+    }
+
+    pub fn orientations(&self, shape: Shape) -> usize {
+        self.pieces[shape.0].orientations.len()
+    }
+
+    pub fn rows(&self, shape: Shape, orientation: usize) -> &[u64] {
+        &self.pieces[shape.0].orientations[orientation]
+    }
+
+    pub fn filled(&self, shape: Shape, orientation: usize, y: isize, x: isize) -> bool {
+        self.rows(shape, orientation)[y as usize] & (1 << x) != 0
+    }
+
+    pub fn kicks(&self, shape: Shape, orientation: usize, clockwise: bool) -> &[(isize, isize)] {
+        &self.pieces[shape.0].kicks[orientation][clockwise as usize]
+    }
+
+    pub fn bounds(&self, shape: Shape) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        //The rows and columns the spawn orientation actually uses
+        let rows = self.rows(shape, 0);
+        let top = rows.iter().position(|row| *row != 0).unwrap();
+        let bottom = rows.iter().rposition(|row| *row != 0).unwrap();
+        let columns = rows.iter().fold(0, |all, row| all | row);
+        let left = columns.trailing_zeros() as usize;
+        let right = 63 - columns.leading_zeros() as usize;
+        (top..bottom + 1, left..right + 1)
+    }
+
+    pub fn preview_size(&self) -> (usize, usize) {
+        //Big enough to show any piece in the set as it spawns
+        self.shapes()
+            .map(|shape| self.bounds(shape))
+            .fold((0, 0), |(h, w), (rows, columns)| {
+                (std::cmp::max(h, rows.len()), std::cmp::max(w, columns.len()))
+            })
+    }
+}
@@ -0,0 +1,143 @@
+use crate::Square;
+
+pub const MAX_WIDTH: usize = 32;
+
+//Each row is a bitmask with bit x set when column x is filled. The colors are kept in a
+//separate layer that only drawing cares about.
+#[derive(Clone)]
+pub struct Playfield {
+    width: usize,
+    full: u64,
+    rows: Vec<u64>,
+    colors: Vec<Square>,
+}
+
+impl Playfield {
+    pub fn new(width: usize, rows: usize) -> Playfield {
+        assert!(width <= MAX_WIDTH);
+
+        Playfield {
+            width,
+            full: (1 << width) - 1,
+            rows: vec![0; rows],
+            colors: vec![Square::EMPTY; width * rows],
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn row(&self, y: usize) -> u64 {
+        self.rows[y]
+    }
+
+    pub fn get(&self, y: usize, x: usize) -> Square {
+        if self.rows[y] & (1 << x) != 0 {
+            self.colors[y * self.width + x]
+        } else {
+            Square::EMPTY
+        }
+    }
+
+    fn shifted(&self, mask: u64, x: isize) -> Option<u64> {
+        //Moves a piece row to column x, None if any of it ends up past a wall
+        let shifted = if x < 0 {
+            if x <= -64 || mask & ((1 << -x) - 1) != 0 {
+                return None;
+            }
+            mask >> -x
+        } else if x < 64 {
+            mask << x
+        } else {
+            return None;
+        };
+
+        if shifted & !self.full != 0 {
+            None
+        } else {
+            Some(shifted)
+        }
+    }
+
+    pub fn collides(&self, y: isize, x: isize, piece: &[u64]) -> bool {
+        for (dy, mask) in piece.iter().enumerate() {
+            if *mask == 0 {
+                continue;
+            }
+
+            let row = y + dy as isize;
+            if row < 0 || row >= self.rows.len() as isize {
+                return true;
+            }
+
+            match self.shifted(*mask, x) {
+                Some(shifted) if self.rows[row as usize] & shifted == 0 => {}
+                _ => return true,
+            }
+        }
+        false
+    }
+
+    pub fn place(&mut self, y: isize, x: isize, piece: &[u64], square: Square) {
+        //Cells that fall outside the playfield are dropped
+        for (dy, mask) in piece.iter().enumerate() {
+            let row = y + dy as isize;
+            if row < 0 || row >= self.rows.len() as isize {
+                continue;
+            }
+
+            for dx in 0..crate::pieces::MAX_SIZE as isize {
+                let column = x + dx;
+                if mask & (1 << dx) != 0 && 0 <= column && column < self.width as isize {
+                    self.rows[row as usize] |= 1 << column;
+                    self.colors[row as usize * self.width + column as usize] = square;
+                }
+            }
+        }
+    }
+
+    pub fn any_filled(&self, rows: std::ops::Range<usize>) -> bool {
+        self.rows[rows].iter().any(|row| *row != 0)
+    }
+
+    pub fn clear_lines(&mut self) -> usize {
+        //Slides every incomplete row down over the complete ones, bottom up
+        let mut write = self.rows.len();
+        for read in (0..self.rows.len()).rev() {
+            if self.rows[read] == self.full {
+                continue;
+            }
+
+            write -= 1;
+            if write != read {
+                self.rows[write] = self.rows[read];
+                let (from, to) = (read * self.width, write * self.width);
+                self.colors.copy_within(from..from + self.width, to);
+            }
+        }
+
+        let cleared = write;
+        for y in 0..cleared {
+            self.rows[y] = 0;
+        }
+        cleared
+    }
+
+    pub fn push_row(&mut self, hole: usize, square: Square) {
+        //Everything moves up a row and the new one goes in at the bottom
+        let bottom = self.rows.len() - 1;
+
+        self.rows.rotate_left(1);
+        self.colors.rotate_left(self.width);
+
+        self.rows[bottom] = self.full & !(1 << hole);
+        for x in 0..self.width {
+            self.colors[bottom * self.width + x] = square;
+        }
+    }
+}
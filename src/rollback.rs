@@ -0,0 +1,225 @@
+use crate::game::Game;
+use crate::Action;
+use std::collections::HashMap;
+
+//Both boards are played on both machines from nothing but the inputs. The other side's inputs
+//are guessed to be nothing until they arrive, and if a guess was wrong the boards are put back
+//to the last frame both sides agree on and played forward again.
+
+const ACTIONS: [Action; 7] = [
+    Action::Left,
+    Action::Right,
+    Action::SoftDrop,
+    Action::HardDrop,
+    Action::RotateCW,
+    Action::RotateCCW,
+    Action::Hold,
+];
+
+//How far ahead of the other side we can guess before waiting for them
+const MAX_AHEAD: usize = 30;
+
+//Agreed frames between comparing hashes
+const HASH_FRAMES: usize = 100;
+
+pub fn encode(action: Option<Action>) -> usize {
+    action.map_or(0, |action| ACTIONS.iter().position(|a| *a == action).unwrap() + 1)
+}
+
+pub fn decode(code: usize) -> Option<Action> {
+    ACTIONS.get(code.wrapping_sub(1)).copied()
+}
+
+fn step(games: &mut [Game; 2], inputs: [Option<Action>; 2]) -> Option<usize> {
+    //One frame for both boards, returns whose game ended
+    for i in 0..2 {
+        if games[i].tick(inputs[i]).is_some() {
+            return Some(i);
+        }
+        let sent = games[i].attack.take_outgoing();
+        games[1 - i].attack.receive(sent);
+    }
+    None
+}
+
+pub struct Session {
+    //The boards as far as we've guessed, which is what gets drawn
+    pub games: [Game; 2],
+    pub frame: usize,
+    pub local: usize,
+    //The boards at the last frame we have both inputs for
+    confirmed: [Game; 2],
+    known: usize,
+    inputs: [Vec<Option<Action>>; 2],
+    guessed_over: bool,
+    pub loser: Option<usize>,
+    pub rollbacks: usize,
+    hashes: HashMap<usize, u64>,
+    their_hashes: HashMap<usize, u64>,
+    pub desync: Option<usize>,
+}
+
+impl Session {
+    pub fn new(games: [Game; 2], local: usize) -> Session {
+        Session {
+            confirmed: games.clone(),
+            games,
+            frame: 0,
+            local,
+            known: 0,
+            inputs: [Vec::new(), Vec::new()],
+            guessed_over: false,
+            loser: None,
+            rollbacks: 0,
+            hashes: HashMap::new(),
+            their_hashes: HashMap::new(),
+            desync: None,
+        }
+    }
+
+    fn input(&self, player: usize, frame: usize) -> Option<Action> {
+        self.inputs[player].get(frame).copied().flatten()
+    }
+
+    pub fn can_advance(&self) -> bool {
+        self.loser.is_none() && self.frame < self.known + MAX_AHEAD
+    }
+
+    pub fn advance(&mut self, action: Option<Action>) -> Vec<(usize, u64)> {
+        //Plays our input for the next frame, guessing theirs if it isn't here yet. Returns
+        //hashes to send them.
+        self.inputs[self.local].push(action);
+        if !self.guessed_over {
+            let inputs = [self.input(0, self.frame), self.input(1, self.frame)];
+            self.guessed_over = step(&mut self.games, inputs).is_some();
+        }
+        self.frame += 1;
+        self.settle(false)
+    }
+
+    pub fn remote(&mut self, frame: usize, action: Option<Action>) -> Vec<(usize, u64)> {
+        //Takes their input for a frame, which arrive in order
+        let remote = 1 - self.local;
+        if frame != self.inputs[remote].len() {
+            return Vec::new();
+        }
+        self.inputs[remote].push(action);
+        self.settle(frame < self.frame && action.is_some())
+    }
+
+    fn settle(&mut self, wrong: bool) -> Vec<(usize, u64)> {
+        let hashes = self.confirm();
+        if self.loser.is_some() {
+            //Nothing past the end counts, whatever was guessed
+            self.games = self.confirmed.clone();
+        } else if wrong {
+            self.rollbacks += 1;
+            self.games = self.confirmed.clone();
+            self.guessed_over = false;
+            for f in self.known..self.frame {
+                if !self.guessed_over {
+                    let inputs = [self.input(0, f), self.input(1, f)];
+                    self.guessed_over = step(&mut self.games, inputs).is_some();
+                }
+            }
+        }
+        hashes
+    }
+
+    fn confirm(&mut self) -> Vec<(usize, u64)> {
+        //Moves the agreed boards up to the last frame both inputs are in for
+        let mut hashes = Vec::new();
+        let both = std::cmp::min(self.inputs[0].len(), self.inputs[1].len());
+        while self.known < both && self.loser.is_none() {
+            let inputs = [self.input(0, self.known), self.input(1, self.known)];
+            self.loser = step(&mut self.confirmed, inputs);
+            self.known += 1;
+
+            if self.known.is_multiple_of(HASH_FRAMES) {
+                let hash = self.confirmed[0].hash() ^ self.confirmed[1].hash().rotate_left(1);
+                self.hashes.insert(self.known, hash);
+                hashes.push((self.known, hash));
+                self.check(self.known);
+            }
+        }
+        hashes
+    }
+
+    pub fn their_hash(&mut self, frame: usize, hash: u64) {
+        self.their_hashes.insert(frame, hash);
+        self.check(frame);
+    }
+
+    fn check(&mut self, frame: usize) {
+        if let (Some(ours), Some(theirs)) = (self.hashes.get(&frame), self.their_hashes.get(&frame)) {
+            if ours != theirs {
+                self.desync.get_or_insert(frame);
+            }
+            self.hashes.remove(&frame);
+            self.their_hashes.remove(&frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Mode, Options, Rules};
+    use std::collections::VecDeque;
+
+    //The second side's inputs take this many frames to get to the first
+    const LAG: usize = 12;
+
+    fn recorded(player: usize, frame: usize) -> Option<Action> {
+        //A key every so often, leaving hard dropped pieces time to lock, with the two players out
+        //of step
+        match frame % (16 + player * 5) {
+            0 => decode((frame / 16 + player) % 8),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn sessions_agree_when_one_lags() {
+        let options = Options::new(Mode::Marathon, Rules::modern(), 0);
+        let game = || Game::new(&options, 7);
+        let mut sessions = [Session::new([game(), game()], 0), Session::new([game(), game()], 1)];
+
+        //Inputs on their way to the other side, with the frame they get there
+        let mut wires = [VecDeque::new(), VecDeque::new()];
+        let mut hashes = [Vec::new(), Vec::new()];
+        for now in 0..3000 {
+            for (side, session) in sessions.iter_mut().enumerate() {
+                if session.can_advance() {
+                    let (frame, action) = (session.frame, recorded(side, session.frame));
+                    hashes[side].extend(session.advance(action));
+                    wires[side].push_back((now + side * LAG, frame, action));
+                }
+            }
+            for side in 0..2 {
+                while wires[side].front().is_some_and(|(at, _, _)| *at <= now) {
+                    let (_, frame, action) = wires[side].pop_front().unwrap();
+                    hashes[1 - side].extend(sessions[1 - side].remote(frame, action));
+                }
+            }
+        }
+        //Whatever's still on the way arrives, so both sides end up agreeing on the same frames
+        for side in 0..2 {
+            for (_, frame, action) in wires[side].drain(..) {
+                hashes[1 - side].extend(sessions[1 - side].remote(frame, action));
+            }
+        }
+
+        for side in 0..2 {
+            for (frame, hash) in hashes[1 - side].iter() {
+                sessions[side].their_hash(*frame, *hash);
+            }
+        }
+        assert!(sessions[0].rollbacks > 0);
+        assert!(sessions[0].confirmed.iter().all(|game| game.board.locked > 0));
+        assert!(!hashes[0].is_empty());
+        assert_eq!(hashes[0], hashes[1]);
+        assert_eq!(sessions[0].loser, sessions[1].loser);
+        assert!(sessions.iter().all(|session| session.desync.is_none()));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::ai::Bot;
+use crate::game::Game;
+use crate::{Action, Board, Controller, Options, Square, Windows};
+use ncurses::*;
+use rand::seq::SliceRandom;
+use rand::{thread_rng, Rng};
+use std::{thread, time};
+
+pub const BOTS: usize = 15;
+pub const MAX_BOTS: usize = 48;
+
+//Bots that aren't given a difficulty are a mix of these
+const DIFFICULTIES: [&str; 3] = ["easy", "medium", "easy"];
+
+//Who an attack goes to
+#[derive(Copy, Clone, PartialEq)]
+pub enum Targeting {
+    Random,
+    //Whoever is sending garbage this way
+    Attackers,
+    //Whoever has the tallest stack, to finish them off
+    KOs,
+}
+
+impl Targeting {
+    const ALL: [Targeting; 3] = [Targeting::Random, Targeting::Attackers, Targeting::KOs];
+
+    pub fn parse(name: &str) -> Option<Targeting> {
+        Targeting::ALL.iter().copied().find(|t| t.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Targeting::Random => "random",
+            Targeting::Attackers => "attackers",
+            Targeting::KOs => "kos",
+        }
+    }
+
+    fn next(self) -> Targeting {
+        let i = Targeting::ALL.iter().position(|t| *t == self).unwrap();
+        Targeting::ALL[(i + 1) % Targeting::ALL.len()]
+    }
+}
+
+//How the player did, places count down from everyone to 1
+pub struct Outcome {
+    pub game: Game,
+    pub place: Option<usize>,
+    pub players: usize,
+    pub kos: usize,
+}
+
+fn stack(board: &Board) -> usize {
+    //How many rows are taken up from the bottom
+    let rows = board.rows();
+    (0..rows).find(|y| board.field.row(*y) != 0).map_or(0, |top| rows - top)
+}
+
+struct Players {
+    games: Vec<Game>,
+    strategies: Vec<Targeting>,
+    targets: Vec<usize>,
+    //Who last sent garbage to each player, so they get the KO
+    attackers: Vec<Option<usize>>,
+    places: Vec<Option<usize>>,
+    kos: Vec<usize>,
+}
+
+impl Players {
+    fn alive(&self) -> usize {
+        self.places.iter().filter(|p| p.is_none()).count()
+    }
+
+    fn pick(&self, from: usize) -> Option<usize> {
+        let others: Vec<usize> = (0..self.games.len())
+            .filter(|i| *i != from && self.places[*i].is_none())
+            .collect();
+        let attackers: Vec<usize> = others
+            .iter()
+            .copied()
+            .filter(|i| self.targets[*i] == from)
+            .collect();
+
+        match self.strategies[from] {
+            Targeting::Attackers if !attackers.is_empty() => {
+                attackers.choose(&mut thread_rng()).copied()
+            }
+            Targeting::KOs => others.iter().copied().max_by_key(|i| {
+                stack(&self.games[*i].board) + self.games[*i].attack.pending()
+            }),
+            _ => others.choose(&mut thread_rng()).copied(),
+        }
+    }
+
+    fn knock_out(&mut self, i: usize) {
+        self.places[i] = Some(self.alive());
+        if let Some(attacker) = self.attackers[i].filter(|a| self.places[*a].is_none()) {
+            self.kos[attacker] += 1;
+        }
+    }
+}
+
+fn draw_mini(players: &Players, i: usize, window: WINDOW) {
+    //Two rows to a character, with the falling piece in it
+    let board = &players.games[i].board;
+    let buffer = board.rules.buffer;
+    let visible = board.rows() - buffer;
+    let player = &board.player;
+    let size = board.size(player);
+
+    let square = |y: usize, x: usize| -> Square {
+        let (p_y, p_x) = (y as isize - player.y, x as isize - player.x);
+        let falling = (0..size).contains(&p_y) && (0..size).contains(&p_x);
+        if players.places[i].is_none() && falling && board.filled(player, p_y, p_x) {
+            Square::FULL(player.shape)
+        } else {
+            board.field.get(y, x)
+        }
+    };
+
+    let mut label = format!("{}", i);
+    if players.targets[0] == i {
+        label.push_str(" <");
+    }
+    if players.targets[i] == 0 && players.places[i].is_none() {
+        label.push_str(" !");
+    }
+    mvwprintw(window, 0, 0, &format!("{:<w$}", label, w = board.rules.width));
+
+    for row in 0..visible.div_ceil(2) {
+        wmove(window, 1 + row as i32, 0);
+        for column in 0..board.rules.width {
+            let top = buffer + row * 2;
+            let bottom = (top + 1 < board.rows()).then(|| square(top + 1, column));
+            let shown = match square(top, column) {
+                Square::EMPTY => bottom,
+                square => Some(square),
+            };
+            match shown {
+                Some(Square::FULL(s)) => {
+                    let color = board.pieces.color(s);
+                    wattron(window, color);
+                    waddch(window, board.pieces.glyph(s));
+                    wattroff(window, color);
+                }
+                Some(Square::GARBAGE) => {
+                    wattron(window, COLOR_PAIR(8));
+                    waddch(window, '#' as chtype);
+                    wattroff(window, COLOR_PAIR(8));
+                }
+                _ => {
+                    waddch(window, '.' as chtype);
+                }
+            }
+        }
+    }
+
+    if let Some(place) = players.places[i] {
+        let out = format!("#{}", place);
+        let middle = 1 + visible as i32 / 4;
+        wattron(window, A_STANDOUT());
+        mvwprintw(window, middle, (board.rules.width as i32 - out.len() as i32) / 2, &out);
+        wattroff(window, A_STANDOUT());
+    }
+    wrefresh(window);
+}
+
+fn layout(options: &Options, count: usize) -> Vec<WINDOW> {
+    //Windows for the mini boards either side of the player's, filled from the top corners in.
+    //Bots that don't fit still play, they just aren't shown.
+    let width = options.rules.width as i32;
+    let height = (options.rules.height as i32 + 1) / 2 + 2;
+    let (_, preview_width) = options.pieces.preview_size();
+    let board_x = COLS() / 2 - (width + 2) / 2;
+    let left_edge = board_x - preview_width as i32 - 5;
+    let right_edge = board_x + width + 2 + 12;
+
+    let per_row = |space: i32| std::cmp::max(space / (width + 2), 0);
+    let (left, right) = (per_row(left_edge), per_row(COLS() - right_edge));
+    let mut slots = Vec::new();
+    for row in 0..std::cmp::max(LINES() / height, 1) {
+        let y = row * height;
+        for column in 0..std::cmp::max(left, right) {
+            if column < left {
+                slots.push((y, left_edge - (column + 1) * (width + 2)));
+            }
+            if column < right {
+                slots.push((y, right_edge + column * (width + 2)));
+            }
+        }
+    }
+    slots.truncate(count);
+    slots
+        .iter()
+        .map(|(y, x)| newwin(height - 1, width, *y, *x))
+        .collect()
+}
+
+pub fn play(options: &Options, bots: usize) -> Outcome {
+    //Everyone gets the same pieces, the player is number 0
+    let seed = options
+        .seed
+        .unwrap_or_else(|| thread_rng().gen_range(0, 1_000_000));
+    let count = bots + 1;
+
+    let mut controllers = vec![if options.ai {
+        Controller::Bot(Bot::new(options.weights))
+    } else {
+        Controller::Keys(Action::from_key)
+    }];
+    for i in 0..bots {
+        let difficulty = options
+            .opponent
+            .as_deref()
+            .unwrap_or(DIFFICULTIES[i % DIFFICULTIES.len()]);
+        controllers.push(Controller::Bot(Bot::difficulty(options.weights, difficulty).unwrap()));
+    }
+
+    let mut strategies = vec![options.target];
+    strategies.extend((0..bots).map(|_| *Targeting::ALL.choose(&mut thread_rng()).unwrap()));
+    let mut players = Players {
+        games: (0..count).map(|_| Game::new(options, seed)).collect(),
+        strategies,
+        targets: vec![0; count],
+        attackers: vec![None; count],
+        places: vec![None; count],
+        kos: vec![0; count],
+    };
+    for i in 0..count {
+        players.targets[i] = players.pick(i).unwrap_or(0);
+    }
+
+    let windows = Windows::at(&options.rules, &options.pieces, COLS() / 2, "YOU");
+    let minis = layout(options, bots);
+
+    let ten_millis = time::Duration::from_millis(10);
+
+    loop {
+        let key = getch();
+        match key {
+            27 => break,
+            //Tab changes who the player's attacks go to
+            9 => players.strategies[0] = players.strategies[0].next(),
+            _ => {}
+        }
+
+        for (i, controller) in controllers.iter_mut().enumerate() {
+            if players.places[i].is_some() {
+                continue;
+            }
+            let action = controller.action(key, &players.games[i]);
+            if players.games[i].tick(action).is_some() {
+                players.knock_out(i);
+                continue;
+            }
+
+            //Who to go after is looked at again every time there's something to send
+            let sent = players.games[i].attack.take_outgoing();
+            if sent > 0 {
+                if let Some(target) = players.pick(i) {
+                    players.targets[i] = target;
+                    players.games[target].attack.receive(sent);
+                    players.attackers[target] = Some(i);
+                }
+            }
+            if players.places[players.targets[i]].is_some() {
+                players.targets[i] = players.pick(i).unwrap_or(i);
+            }
+        }
+
+        if players.places[0].is_some() || players.alive() <= 1 {
+            break;
+        }
+
+        let game = &players.games[0];
+        game.board.draw(&windows);
+        game.board.draw_next(&windows);
+        game.board.draw_held(&windows);
+        game.board.draw_score(&windows);
+        game.attack.draw(windows.meter);
+
+        let status = format!(
+            "TARGET {}  KOS {}  LEFT {}",
+            players.strategies[0].name().to_uppercase(),
+            players.kos[0],
+            players.alive()
+        );
+        mvprintw(LINES() - 1, COLS() / 2 - 16, &format!("{:^32}", status));
+
+        refresh();
+        for (i, mini) in minis.iter().enumerate() {
+            draw_mini(&players, i + 1, *mini);
+        }
+
+        thread::sleep(ten_millis);
+    }
+
+    for mini in minis {
+        delwin(mini);
+    }
+
+    //Still standing at the end is a win, quitting early doesn't get a place
+    let place = match players.places[0] {
+        Some(place) => Some(place),
+        None if players.alive() == 1 => Some(1),
+        None => None,
+    };
+    let kos = players.kos[0];
+    Outcome {
+        game: players.games.swap_remove(0),
+        place,
+        players: count,
+        kos,
+    }
+}
@@ -0,0 +1,245 @@
+use ncurses::*;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const TABLE_SIZE: usize = 10;
+
+pub const MODES: [&str; 4] = ["marathon", "survival", "classic", "classic-survival"];
+
+#[derive(Clone)]
+pub struct Record {
+    pub mode: String,
+    pub name: String,
+    pub score: usize,
+    pub lines: usize,
+    pub level: usize,
+    pub ticks: usize,
+    pub date: u64,
+    pub seed: u64,
+    pub replay: Option<String>,
+}
+
+impl Record {
+    pub fn new(mode: &str, score: usize, lines: usize, level: usize, ticks: usize, seed: u64) -> Record {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Record {
+            mode: mode.to_string(),
+            name: String::new(),
+            score,
+            lines,
+            level,
+            ticks,
+            date,
+            seed,
+            replay: None,
+        }
+    }
+
+    fn rank(&self) -> (usize, usize) {
+        //Survival is about lasting as long as possible, everything else is about points
+        if self.mode.ends_with("survival") {
+            (self.ticks, self.lines)
+        } else {
+            (self.score, self.lines)
+        }
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.mode,
+            self.name,
+            self.score,
+            self.lines,
+            self.level,
+            self.ticks,
+            self.date,
+            self.seed,
+            self.replay.as_ref().map_or("", |r| r.as_str())
+        )
+    }
+
+    fn from_line(line: &str) -> Option<Record> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 9 {
+            return None;
+        }
+
+        Some(Record {
+            mode: fields[0].to_string(),
+            name: fields[1].to_string(),
+            score: fields[2].parse().ok()?,
+            lines: fields[3].parse().ok()?,
+            level: fields[4].parse().ok()?,
+            ticks: fields[5].parse().ok()?,
+            date: fields[6].parse().ok()?,
+            seed: fields[7].parse().ok()?,
+            replay: if fields[8].is_empty() {
+                None
+            } else {
+                Some(fields[8].to_string())
+            },
+        })
+    }
+}
+
+fn data_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(dir.join("ncurses_tetris").join("scores.tsv"))
+}
+
+pub fn load() -> Vec<Record> {
+    data_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|text| text.lines().filter_map(Record::from_line).collect())
+        .unwrap_or_default()
+}
+
+pub fn save(records: &[Record]) -> io::Result<()> {
+    let path = match data_path() {
+        Some(path) => path,
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "no data directory")),
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = fs::File::create(path)?;
+    for record in records {
+        writeln!(file, "{}", record.to_line())?;
+    }
+    Ok(())
+}
+
+pub fn table(records: &[Record], mode: &str) -> Vec<Record> {
+    let mut table: Vec<Record> = records.iter().filter(|r| r.mode == mode).cloned().collect();
+    table.sort_by_key(|r| std::cmp::Reverse(r.rank()));
+    table.truncate(TABLE_SIZE);
+    table
+}
+
+pub fn is_record(records: &[Record], record: &Record) -> bool {
+    let table = table(records, &record.mode);
+    table.len() < TABLE_SIZE || table.iter().any(|r| record.rank() > r.rank())
+}
+
+pub fn insert(records: &mut Vec<Record>, record: Record) {
+    //Only the top of each table is worth keeping around
+    let mode = record.mode.clone();
+    records.push(record);
+
+    let kept = table(records, &mode);
+    records.retain(|r| r.mode != mode);
+    records.extend(kept);
+}
+
+pub fn prompt_name() -> String {
+    let mut max_y = 0;
+    let mut max_x = 0;
+    getmaxyx(stdscr(), &mut max_y, &mut max_x);
+
+    let window = newwin(5, 24, (max_y - 5) / 2, (max_x - 24) / 2);
+    box_(window, 0, 0);
+    mvwprintw(window, 1, 6, "NEW RECORD!");
+    mvwprintw(window, 3, 2, "Name: ");
+    wrefresh(window);
+
+    nodelay(stdscr(), false);
+    echo();
+    curs_set(CURSOR_VISIBILITY::CURSOR_VISIBLE);
+
+    let mut name = String::new();
+    wgetnstr(window, &mut name, 12);
+
+    noecho();
+    curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+    delwin(window);
+
+    //Tabs would break the data file
+    let name = name.replace('\t', " ").trim().to_string();
+    if name.is_empty() {
+        "anonymous".to_string()
+    } else {
+        name
+    }
+}
+
+fn format_time(ticks: usize) -> String {
+    let seconds = ticks / 100;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn format_date(date: u64) -> String {
+    //Days since the epoch to a civil date
+    let days = (date / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+pub fn draw_table(records: &[Record], mode: &str, highlight: Option<&Record>) {
+    let mut max_y = 0;
+    let mut max_x = 0;
+    getmaxyx(stdscr(), &mut max_y, &mut max_x);
+
+    let height = TABLE_SIZE as i32 + 6;
+    let width = 64;
+    let window = newwin(height, width, (max_y - height) / 2, (max_x - width) / 2);
+    box_(window, 0, 0);
+
+    mvwprintw(window, 1, 2, &format!("HIGH SCORES - {}", mode.to_uppercase()));
+    mvwprintw(
+        window,
+        3,
+        2,
+        &format!(
+            "{:>2} {:<12} {:>8} {:>5} {:>5} {:>6} {:>10} {:>6}",
+            "#", "NAME", "SCORE", "LINES", "LEVEL", "TIME", "DATE", "SEED"
+        ),
+    );
+
+    for (i, record) in table(records, mode).iter().enumerate() {
+        let current = highlight.is_some_and(|h| h.to_line() == record.to_line());
+        if current {
+            wattron(window, A_STANDOUT());
+        }
+        mvwprintw(
+            window,
+            4 + i as i32,
+            2,
+            &format!(
+                "{:>2} {:<12} {:>8} {:>5} {:>5} {:>6} {:>10} {:>6}",
+                i + 1,
+                record.name,
+                record.score,
+                record.lines,
+                record.level,
+                format_time(record.ticks),
+                format_date(record.date),
+                record.seed
+            ),
+        );
+        if current {
+            wattroff(window, A_STANDOUT());
+        }
+    }
+
+    mvwprintw(window, height - 2, 2, "<- -> change mode, any other key to go back");
+    wrefresh(window);
+    delwin(window);
+}
@@ -0,0 +1,68 @@
+use crate::{Action, Board, Direction, Player};
+use std::collections::{HashSet, VecDeque};
+
+//Somewhere the piece can come to rest, and the fewest inputs that get it there
+pub struct Placement {
+    pub player: Player,
+    pub path: Vec<Action>,
+}
+
+pub fn placements(board: &Board, player: &Player) -> Vec<Placement> {
+    //Breadth first over every position and orientation reachable with moves, soft drops and
+    //rotations (kicks included), so tucks and spins are found along with plain drops.
+    //Each state remembers how it was first reached to rebuild its path.
+    let mut states = vec![(*player, None)];
+    let mut seen = HashSet::new();
+    let mut landed = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut found = Vec::new();
+
+    seen.insert((player.x, player.y, player.orientation));
+    queue.push_back(0);
+
+    while let Some(index) = queue.pop_front() {
+        let current = states[index].0;
+
+        let next = [
+            (Action::Left, board.moved(&current, Direction::W)),
+            (Action::Right, board.moved(&current, Direction::E)),
+            (Action::RotateCCW, board.rotated(&current, Direction::W)),
+            (Action::RotateCW, board.rotated(&current, Direction::E)),
+            (Action::SoftDrop, board.moved(&current, Direction::S)),
+        ];
+
+        //Pieces that can't go down any further have landed. Different orientations can fill
+        //the same cells, those only count once.
+        if next[4].1.is_none() && landed.insert(board.cells(&current)) {
+            let mut path = Vec::new();
+            let mut at = index;
+            while let (_, Some((parent, action))) = states[at] {
+                path.push(action);
+                at = parent;
+            }
+            path.reverse();
+            found.push(Placement {
+                player: current,
+                path,
+            });
+        }
+
+        for (action, p) in next.iter() {
+            if let Some(p) = p {
+                if seen.insert((p.x, p.y, p.orientation)) {
+                    queue.push_back(states.len());
+                    states.push((*p, Some((index, *action))));
+                }
+            }
+        }
+    }
+    found
+}
+
+pub fn path_to(board: &Board, player: &Player, cells: &[(isize, isize)]) -> Option<Vec<Action>> {
+    //How to get the piece onto exactly these cells, if it can get there at all
+    placements(board, player)
+        .into_iter()
+        .find(|placement| board.cells(&placement.player) == cells)
+        .map(|placement| placement.path)
+}
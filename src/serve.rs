@@ -0,0 +1,337 @@
+use crate::game::Game;
+use crate::pieces::Shape;
+use crate::{parse_options, Action, Board, Mode, Options, Player, Square};
+use ncurses::{KEY_DOWN, KEY_LEFT, KEY_RIGHT, KEY_UP};
+use rand::{thread_rng, Rng};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const PORT: u16 = 2323;
+
+//Telnet commands, everything else coming in is a key
+const IAC: u8 = 255;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const WILL: u8 = 251;
+const DONT: u8 = 254;
+const IP: u8 = 244;
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const LINEMODE: u8 = 34;
+
+//Escape codes for each color pair, in the same order as initialize_color
+const STYLES: [&str; 9] = ["0", "36", "33", "35", "32", "31", "34", "37", "30;47"];
+
+//The game drawn a cell at a time, then written out with escape codes all at once
+struct Screen {
+    cells: Vec<Vec<(char, i16, bool)>>,
+}
+
+impl Screen {
+    fn new(height: usize, width: usize) -> Screen {
+        Screen {
+            cells: vec![vec![(' ', 0, false); width]; height],
+        }
+    }
+
+    fn put(&mut self, y: usize, x: usize, c: char, pair: i16, standout: bool) {
+        if let Some(cell) = self.cells.get_mut(y).and_then(|row| row.get_mut(x)) {
+            *cell = (c, pair, standout);
+        }
+    }
+
+    fn text(&mut self, y: usize, x: usize, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            self.put(y, x + i, c, 0, false);
+        }
+    }
+
+    fn boxed(&mut self, y: usize, x: usize, height: usize, width: usize) {
+        for i in 1..width - 1 {
+            self.put(y, x + i, '-', 0, false);
+            self.put(y + height - 1, x + i, '-', 0, false);
+        }
+        for i in 1..height - 1 {
+            self.put(y + i, x, '|', 0, false);
+            self.put(y + i, x + width - 1, '|', 0, false);
+        }
+        let (bottom, right) = (y + height - 1, x + width - 1);
+        for (corner_y, corner_x) in [(y, x), (y, right), (bottom, x), (bottom, right)] {
+            self.put(corner_y, corner_x, '+', 0, false);
+        }
+    }
+
+    fn ansi(&self) -> String {
+        //Drawn over the last one from the top left, which is less flicker than clearing
+        let mut out = String::from("\x1b[H");
+        for row in &self.cells {
+            let mut style = (0, false);
+            for &(c, pair, standout) in row {
+                if (pair, standout) != style {
+                    let reverse = if standout { ";7" } else { "" };
+                    out += &format!("\x1b[0;{}{}m", STYLES[pair as usize], reverse);
+                    style = (pair, standout);
+                }
+                out.push(c);
+            }
+            out += "\x1b[0m\x1b[K\r\n";
+        }
+        out
+    }
+}
+
+fn preview(screen: &mut Screen, board: &Board, top: usize, left: usize, shape: Shape) {
+    //Same as draw_preview, centered in the box
+    let (height, width) = board.pieces.preview_size();
+    let (rows, columns) = board.pieces.bounds(shape);
+    let top = top + (height - rows.len()) / 2;
+    let left = left + (width - columns.len()) / 2;
+    let pair = board.pieces.pair(shape);
+
+    for y in rows.clone() {
+        for x in columns.clone() {
+            if board.pieces.filled(shape, 0, y as isize, x as isize) {
+                screen.put(top + y - rows.start, left + x - columns.start, '@', pair, false);
+            }
+        }
+    }
+}
+
+fn draw(board: &Board) -> Screen {
+    let (preview_height, preview_width) = board.pieces.preview_size();
+    let (width, buffer) = (board.rules.width, board.rules.buffer as isize);
+    let rows = board.rows() - board.rules.buffer;
+    let left = preview_width + 4;
+    let right = left + width + 4;
+    let mut screen = Screen::new(rows + 3, right + 9);
+
+    screen.text(0, left + (width + 2) / 2 - 3, "tetris");
+    screen.boxed(1, left, rows + 2, width + 2);
+
+    for y in board.rules.buffer..board.rows() {
+        for x in 0..width {
+            let (row, column) = (y - board.rules.buffer + 2, left + 1 + x);
+            match board.field.get(y, x) {
+                Square::FULL(s) => {
+                    let glyph = board.pieces.glyph(s) as u8 as char;
+                    screen.put(row, column, glyph, board.pieces.pair(s), false);
+                }
+                Square::GARBAGE => screen.put(row, column, '#', 8, false),
+                _ => {}
+            }
+        }
+    }
+
+    let pair = board.pieces.pair(board.player.shape);
+    let glyph = board.pieces.glyph(board.player.shape) as u8 as char;
+    let size = board.size(&board.player);
+    let mut piece = |player: Player, standout| {
+        for y in 0..size {
+            for x in 0..size {
+                let (p_y, p_x) = (y + player.y, x + player.x);
+                if board.visible(p_y, p_x) && board.filled(&player, y, x) {
+                    let (row, column) = ((p_y - buffer + 2) as usize, left + 1 + p_x as usize);
+                    screen.put(row, column, glyph, pair, standout);
+                }
+            }
+        }
+    };
+    if board.rules.ghost {
+        piece(board.bottom_out(), true);
+    }
+    piece(board.player, false);
+
+    screen.text(2, 1, "NEXT");
+    screen.boxed(3, 0, preview_height + 2, preview_width + 2);
+    preview(&mut screen, board, 4, 1, *board.grab_bag.last().unwrap());
+
+    if board.rules.hold {
+        let held = preview_height + 7;
+        screen.text(held - 1, 1, "HELD");
+        screen.boxed(held, 0, preview_height + 2, preview_width + 2);
+        if let Some(shape) = board.held {
+            preview(&mut screen, board, held + 1, 1, shape);
+        }
+    }
+
+    let numbers = [
+        ("LINES", format!("{:>3}", board.lines)),
+        ("LEVEL", format!("{:>3}", board.level())),
+        ("SCORE", format!("{:>7}", board.score)),
+    ];
+    for (i, (label, number)) in numbers.iter().enumerate() {
+        screen.text(2 + i * 5, right, label);
+        screen.boxed(3 + i * 5, right, 3, number.len() + 2);
+        screen.text(4 + i * 5, right + 1, number);
+    }
+
+    screen
+}
+
+//Turns what the client sends into the same key codes getch gives, returns true to quit
+fn keys(bytes: &[u8], keys: &mut VecDeque<i32>) -> bool {
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1).copied()) {
+            (IAC, Some(IP)) => return true,
+            (IAC, Some(SB)) => {
+                //Option negotiation we didn't ask for, skipped up to its end
+                while i < bytes.len() && !(bytes[i] == IAC && bytes.get(i + 1) == Some(&SE)) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            (IAC, Some(WILL..=DONT)) => i += 3,
+            (IAC, _) => i += 2,
+            (27, Some(b'[')) | (27, Some(b'O')) => {
+                let arrow = match bytes.get(i + 2) {
+                    Some(b'A') => Some(KEY_UP),
+                    Some(b'B') => Some(KEY_DOWN),
+                    Some(b'C') => Some(KEY_RIGHT),
+                    Some(b'D') => Some(KEY_LEFT),
+                    _ => None,
+                };
+                keys.extend(arrow);
+                i += 3;
+            }
+            //Escape on its own, Ctrl-C or Ctrl-D
+            (27, _) | (3, _) | (4, _) => return true,
+            (key, _) => {
+                keys.push_back(key as i32);
+                i += 1;
+            }
+        }
+    }
+    false
+}
+
+fn session(mut stream: TcpStream, options: &Options) -> Result<String, String> {
+    stream.set_nodelay(true).map_err(|e| e.to_string())?;
+    stream
+        .set_write_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+
+    let (sender, input) = mpsc::channel();
+    let mut reader = stream.try_clone().map_err(|e| e.to_string())?;
+    thread::spawn(move || {
+        let mut buffer = [0; 256];
+        while let Ok(n) = reader.read(&mut buffer) {
+            if n == 0 || sender.send(buffer[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    //Asking the client to leave echoing to us and send keys as they're pressed, then
+    //clearing the screen and hiding the cursor
+    let mut hello = vec![IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD, IAC, DONT, LINEMODE];
+    hello.extend_from_slice(b"\x1b[2J\x1b[?25l");
+    stream.write_all(&hello).map_err(|e| e.to_string())?;
+
+    let seed = options
+        .seed
+        .unwrap_or_else(|| thread_rng().gen_range(0, 1_000_000));
+    let mut game = Game::new(options, seed);
+    let mut pressed = VecDeque::new();
+    let mut last = String::new();
+
+    let quit = loop {
+        let quit = loop {
+            match input.try_recv() {
+                Ok(bytes) if keys(&bytes, &mut pressed) => break true,
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => return Ok(String::from("left")),
+            }
+        };
+        if quit {
+            break true;
+        }
+
+        //One key a tick, like getch
+        let action = pressed.pop_front().and_then(Action::from_key);
+        let end = game.tick(action);
+
+        let frame = draw(&game.board).ansi();
+        if frame != last {
+            stream.write_all(frame.as_bytes()).map_err(|e| e.to_string())?;
+            last = frame;
+        }
+
+        if end.is_some() {
+            break false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let board = &game.board;
+    let message = match options.mode {
+        _ if quit => format!("You quit with {} lines and {} points.", board.lines, board.score),
+        Mode::Marathon => format!(
+            "You lost at tetris!! You got {} lines and {} points!",
+            board.lines, board.score
+        ),
+        Mode::Survival => format!(
+            "You survived {} seconds of garbage!! You got {} lines!",
+            game.ticks / 100,
+            board.lines
+        ),
+    };
+    let goodbye = format!("\x1b[0m\x1b[2J\x1b[H\x1b[?25h{}\r\n", message);
+    let _ = stream.write_all(goodbye.as_bytes());
+    let _ = stream.shutdown(Shutdown::Both);
+
+    Ok(format!("{} lines, {} points", board.lines, board.score))
+}
+
+pub fn run(args: Vec<String>) {
+    //Everyone who connects gets their own game, played by the rules in the server's flags
+    let mut port = PORT;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .and_then(|port| port.parse().ok())
+                    .expect("--port takes a number");
+            }
+            _ => rest.push(arg),
+        }
+    }
+    let options = Arc::new(parse_options(rest));
+
+    let listener = match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on port {}: {}", port, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Serving games on port {}, play with telnet HOSTNAME {}", port, port);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Could not accept a connection: {}", e);
+                continue;
+            }
+        };
+        let address = stream
+            .peer_addr()
+            .map_or_else(|_| String::from("someone"), |a| a.to_string());
+        let options = options.clone();
+        eprintln!("{} connected", address);
+        thread::spawn(move || match session(stream, &options) {
+            Ok(result) => eprintln!("{} {}", address, result),
+            Err(e) => eprintln!("{} dropped: {}", address, e),
+        });
+    }
+}
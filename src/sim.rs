@@ -0,0 +1,146 @@
+use crate::ai::{Bot, Weights};
+use crate::game::{End, Game};
+use crate::{parse_options, Options};
+
+enum Format {
+    Json,
+    Csv,
+}
+
+//How a game played by the bot went
+pub struct Outcome {
+    pub seed: u64,
+    pub lines: usize,
+    pub score: usize,
+    pub level: usize,
+    pub pieces: usize,
+    pub ticks: usize,
+    //None if it was still going when it hit the piece limit
+    pub end: Option<End>,
+}
+
+impl Outcome {
+    fn end_name(&self) -> &'static str {
+        self.end.map_or("limit", End::name)
+    }
+
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"seed\": {}, \"lines\": {}, \"score\": {}, \"level\": {}, \"pieces\": {}, \"ticks\": {}, \"end\": \"{}\"}}",
+            self.seed,
+            self.lines,
+            self.score,
+            self.level,
+            self.pieces,
+            self.ticks,
+            self.end_name()
+        )
+    }
+
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}",
+            self.seed,
+            self.lines,
+            self.score,
+            self.level,
+            self.pieces,
+            self.ticks,
+            self.end_name()
+        )
+    }
+}
+
+pub fn simulate(options: &Options, weights: &Weights, seed: u64, limit: usize) -> Outcome {
+    //The same game the terminal runs, just without the sleeping and the drawing
+    let mut game = Game::new(options, seed);
+    let mut bot = Bot::new(*weights);
+    bot.delay = 0;
+
+    let end = loop {
+        let action = bot.action(&game.board, game.can_hold);
+        if let Some(end) = game.tick(action) {
+            break Some(end);
+        }
+        if game.stats.pieces >= limit {
+            break None;
+        }
+    };
+
+    Outcome {
+        seed,
+        lines: game.board.lines,
+        score: game.board.score,
+        level: game.board.level(),
+        pieces: game.stats.pieces,
+        ticks: game.ticks,
+        end,
+    }
+}
+
+pub fn run(args: Vec<String>) {
+    //Flags for the simulation itself, everything else is passed on like a normal game
+    let mut games = 10;
+    let mut limit = 1000;
+    let mut format = Format::Json;
+    let mut rest = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--games" => {
+                games = args
+                    .next()
+                    .and_then(|games| games.parse().ok())
+                    .expect("--games takes a number");
+            }
+            "--limit" => {
+                limit = args
+                    .next()
+                    .and_then(|limit| limit.parse().ok())
+                    .expect("--limit takes a number of pieces");
+            }
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("json") => Format::Json,
+                    Some("csv") => Format::Csv,
+                    _ => {
+                        eprintln!("--format is json or csv");
+                        std::process::exit(1);
+                    }
+                };
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    let options = parse_options(rest);
+    let first = options.seed.unwrap_or(0);
+
+    if let Format::Csv = format {
+        println!("seed,lines,score,level,pieces,ticks,end");
+    } else {
+        println!("[");
+    }
+
+    //Each game is printed as soon as it's done so long runs can be watched
+    let mut lines = 0;
+    for i in 0..games {
+        let outcome = simulate(&options, &options.weights, first + i, limit);
+        lines += outcome.lines;
+        match format {
+            Format::Json if i + 1 < games => println!("  {},", outcome.to_json()),
+            Format::Json => println!("  {}", outcome.to_json()),
+            Format::Csv => println!("{}", outcome.to_csv()),
+        }
+    }
+
+    if let Format::Json = format {
+        println!("]");
+    }
+    eprintln!(
+        "{} games, {:.1} lines on average",
+        games,
+        lines as f64 / std::cmp::max(games, 1) as f64
+    );
+}
@@ -0,0 +1,256 @@
+use crate::attack::Attack;
+use crate::game::Game;
+use crate::net::{apply, snapshot};
+use crate::pieces::PieceSet;
+use crate::stats::Stats;
+use crate::{start_terminal, Board, Options, RuleSet, Rules, Windows};
+use ncurses::*;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+pub const PORT: u16 = 7778;
+
+//Ticks between pictures sent to spectators
+const SNAPSHOT_TICKS: usize = 5;
+
+//Anything with a slash in it is a Unix socket, a bare number is a port on every interface
+enum Address {
+    Tcp(String),
+    Unix(String),
+}
+
+impl Address {
+    fn parse(address: &str, listening: bool) -> Address {
+        if address.contains('/') {
+            Address::Unix(address.to_string())
+        } else if address.parse::<u16>().is_ok() {
+            let host = if listening { "0.0.0.0" } else { "localhost" };
+            Address::Tcp(format!("{}:{}", host, address))
+        } else if address.contains(':') {
+            Address::Tcp(address.to_string())
+        } else {
+            Address::Tcp(format!("{}:{}", address, PORT))
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, String),
+}
+
+//Sends a running game to whoever connects. A spectator that stops reading gets dropped
+//rather than holding the game up.
+pub struct Broadcast {
+    listener: Listener,
+    hello: Value,
+    spectators: Vec<Box<dyn Write>>,
+}
+
+impl Broadcast {
+    pub fn new(address: &str, options: &Options) -> Result<Broadcast, String> {
+        let listener = match Address::parse(address, true) {
+            Address::Tcp(address) => {
+                let listener =
+                    TcpListener::bind(&address).map_err(|e| format!("{}: {}", address, e))?;
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                Listener::Tcp(listener)
+            }
+            Address::Unix(path) => {
+                //A socket left over from an earlier game would be in the way
+                let _ = std::fs::remove_file(&path);
+                let listener = UnixListener::bind(&path).map_err(|e| format!("{}: {}", path, e))?;
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                Listener::Unix(listener, path)
+            }
+        };
+
+        //Spectators build the same layout from this
+        let rules = &options.rules;
+        let hello = json!({
+            "type": "hello",
+            "classic": rules.set == RuleSet::Classic,
+            "width": rules.width,
+            "height": rules.height,
+            "buffer": rules.buffer,
+            "pieces": options.pieces.name,
+            "level": options.level,
+        });
+
+        Ok(Broadcast {
+            listener,
+            hello,
+            spectators: Vec::new(),
+        })
+    }
+
+    fn accept(&mut self) -> Option<Box<dyn Write>> {
+        let timeout = Some(Duration::from_millis(10));
+        match &self.listener {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().ok()?;
+                stream.set_nonblocking(false).ok()?;
+                stream.set_write_timeout(timeout).ok()?;
+                stream.set_nodelay(true).ok()?;
+                Some(Box::new(stream))
+            }
+            Listener::Unix(listener, _) => {
+                let (stream, _) = listener.accept().ok()?;
+                stream.set_nonblocking(false).ok()?;
+                stream.set_write_timeout(timeout).ok()?;
+                Some(Box::new(stream))
+            }
+        }
+    }
+
+    fn send(&mut self, message: &Value) {
+        //Spectators that can't keep up or went away are dropped
+        let line = format!("{}\n", message);
+        self.spectators
+            .retain_mut(|spectator| spectator.write_all(line.as_bytes()).is_ok());
+    }
+
+    pub fn update(&mut self, game: &Game) {
+        while let Some(mut spectator) = self.accept() {
+            let hello = format!("{}\n{}\n", self.hello, snapshot(game));
+            if spectator.write_all(hello.as_bytes()).is_ok() {
+                self.spectators.push(spectator);
+            }
+        }
+
+        if game.ticks.is_multiple_of(SNAPSHOT_TICKS) && !self.spectators.is_empty() {
+            self.send(&snapshot(game));
+        }
+    }
+
+    pub fn end(&mut self, game: &Game) {
+        self.send(&snapshot(game));
+        self.send(&json!({"type": "end"}));
+    }
+}
+
+impl Drop for Broadcast {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn read_stats(stats: &mut Stats, message: &Value) -> Option<usize> {
+    stats.pieces = message["pieces"].as_u64()? as usize;
+    stats.keys = message["keys"].as_u64()? as usize;
+    stats.faults = message["faults"].as_u64()? as usize;
+    for (count, value) in stats.counts.iter_mut().zip(message["counts"].as_array()?.iter()) {
+        *count = value.as_u64()? as usize;
+    }
+    Some(message["ticks"].as_u64()? as usize)
+}
+
+pub fn watch(address: &str) {
+    let stream: Box<dyn Read + Send> = match Address::parse(address, false) {
+        Address::Tcp(address) => match TcpStream::connect(&address) {
+            Ok(stream) => Box::new(stream),
+            Err(e) => {
+                eprintln!("Could not connect to {}: {}", address, e);
+                std::process::exit(1);
+            }
+        },
+        Address::Unix(path) => match UnixStream::connect(&path) {
+            Ok(stream) => Box::new(stream),
+            Err(e) => {
+                eprintln!("Could not connect to {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let (sender, messages) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let message: Value = match line.ok().and_then(|l| serde_json::from_str(&l).ok()) {
+                Some(message) => message,
+                None => break,
+            };
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    //The layout comes from the game being watched
+    let hello = match messages.recv() {
+        Ok(hello) if hello["type"] == "hello" => hello,
+        _ => {
+            eprintln!("{} isn't sending a game", address);
+            std::process::exit(1);
+        }
+    };
+    let mut rules = if hello["classic"].as_bool() == Some(true) {
+        Rules::classic()
+    } else {
+        Rules::modern()
+    };
+    let size = |name: &str, default: usize| hello[name].as_u64().map_or(default, |n| n as usize);
+    rules.width = size("width", rules.width);
+    rules.height = size("height", rules.height);
+    rules.buffer = size("buffer", rules.buffer);
+    let pieces = match PieceSet::load(hello["pieces"].as_str().unwrap_or(rules.pieces)) {
+        Ok(pieces) => Arc::new(pieces),
+        Err(e) => {
+            eprintln!("Could not load pieces from {}", e);
+            std::process::exit(1);
+        }
+    };
+    let level = size("level", 0);
+
+    start_terminal();
+    let windows = Windows::new(&rules, &pieces);
+    let mut board = Board::new(rules, pieces.clone(), level, 0);
+    let mut attack = Attack::new();
+    let mut stats = Stats::new(pieces.len());
+    let mut ticks = 0;
+    let mut seen = false;
+
+    let over = loop {
+        match getch() {
+            115 => stats.toggle(windows.stats),
+            27 => break false,
+            _ => {}
+        }
+
+        match messages.recv_timeout(Duration::from_millis(10)) {
+            Ok(message) if message["type"] == "end" => break true,
+            Ok(message) => {
+                if apply(&mut board, &mut attack, &message).is_some() {
+                    ticks = read_stats(&mut stats, &message).unwrap_or(ticks);
+                    seen = true;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break true,
+        }
+
+        if seen {
+            board.draw(&windows);
+            board.draw_next(&windows);
+            board.draw_held(&windows);
+            board.draw_score(&windows);
+            stats.draw(windows.stats, ticks, board.lines, &pieces);
+        }
+    };
+
+    endwin();
+    if over {
+        println!(
+            "The game is over, it ended with {} lines and {} points.",
+            board.lines, board.score
+        );
+    }
+}
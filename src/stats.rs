@@ -0,0 +1,86 @@
+use crate::pieces::{PieceSet, Shape};
+use ncurses::*;
+
+#[derive(Clone)]
+pub struct Stats {
+    pub visible: bool,
+    pub pieces: usize,
+    pub keys: usize,
+    pub faults: usize,
+    pub counts: Vec<usize>,
+}
+
+impl Stats {
+    pub fn new(shapes: usize) -> Stats {
+        Stats {
+            visible: false,
+            pieces: 0,
+            keys: 0,
+            faults: 0,
+            counts: vec![0; shapes],
+        }
+    }
+
+    pub fn piece_locked(&mut self, shape: Shape) {
+        self.pieces += 1;
+        self.counts[shape.0] += 1;
+    }
+
+    pub fn toggle(&mut self, window: WINDOW) {
+        self.visible = !self.visible;
+        if !self.visible {
+            werase(window);
+            wrefresh(window);
+        }
+    }
+
+    pub fn draw(&self, window: WINDOW, ticks: usize, lines: usize, pieces: &PieceSet) {
+        if !self.visible {
+            return;
+        }
+
+        //Ticks are 10ms
+        let seconds = ticks as f64 / 100.0;
+        let minutes = seconds / 60.0;
+
+        let per = |count: usize, over: f64| {
+            if over > 0.0 {
+                count as f64 / over
+            } else {
+                0.0
+            }
+        };
+
+        werase(window);
+        box_(window, 0, 0);
+
+        mvwprintw(
+            window,
+            1,
+            2,
+            &format!("TIME   {:>3}:{:04.1}", ticks / 6000, seconds % 60.0),
+        );
+        mvwprintw(window, 2, 2, &format!("PIECES {:>9}", self.pieces));
+        mvwprintw(window, 3, 2, &format!("PPS    {:>9.2}", per(self.pieces, seconds)));
+        mvwprintw(window, 4, 2, &format!("KPP    {:>9.2}", per(self.keys, self.pieces as f64)));
+        mvwprintw(window, 5, 2, &format!("LPM    {:>9.2}", per(lines, minutes)));
+        mvwprintw(window, 6, 2, &format!("FAULTS {:>9}", self.faults));
+
+        let most = *self.counts.iter().max().unwrap();
+        for (i, shape) in pieces.shapes().enumerate() {
+            let count = self.counts[shape.0];
+            //Bars are scaled so the most common piece fills the space
+            let bar = (count * 10).checked_div(most).unwrap_or(0);
+
+            mvwprintw(window, 8 + i as i32, 2, &format!("{} ", pieces.name(shape)));
+            wattron(window, pieces.color(shape));
+            for _ in 0..bar {
+                waddch(window, '#' as chtype);
+            }
+            wattroff(window, pieces.color(shape));
+            mvwprintw(window, 8 + i as i32, 15, &format!("{:>3}", count));
+        }
+
+        wrefresh(window);
+    }
+}
@@ -0,0 +1,478 @@
+use crate::ai::{self, Weights};
+use crate::game::Game;
+use crate::pieces::{PieceSet, Shape};
+use crate::playfield::Playfield;
+use crate::{Action, Board, Player, Rules, Square};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+//The Tetris Bot Protocol: one JSON message per line over the bot's stdin and stdout.
+//It only knows the standard pieces on a 10x40 board, with y counting up from the bottom.
+
+const ORIENTATIONS: [&str; 4] = ["north", "east", "south", "west"];
+
+//How long a bot gets to introduce itself and get ready before we give up on it
+const STARTUP: Duration = Duration::from_secs(5);
+
+fn offsets(name: &str) -> Option<[(isize, isize); 4]> {
+    //Cells around the piece's center when it points north
+    match name {
+        "I" => Some([(-1, 0), (0, 0), (1, 0), (2, 0)]),
+        "O" => Some([(0, 0), (1, 0), (0, 1), (1, 1)]),
+        "T" => Some([(-1, 0), (0, 0), (1, 0), (0, 1)]),
+        "L" => Some([(-1, 0), (0, 0), (1, 0), (1, 1)]),
+        "J" => Some([(-1, 0), (0, 0), (1, 0), (-1, 1)]),
+        "S" => Some([(-1, 0), (0, 0), (0, 1), (1, 1)]),
+        "Z" => Some([(-1, 1), (0, 1), (0, 0), (1, 0)]),
+        _ => None,
+    }
+}
+
+fn location_cells(rows: usize, location: &Value) -> Option<(String, Vec<(isize, isize)>)> {
+    //The piece and the board cells a location covers, sorted the same way Board::cells is
+    let name = location["type"].as_str()?;
+    let orientation = ORIENTATIONS
+        .iter()
+        .position(|o| Some(*o) == location["orientation"].as_str())?;
+    let x = location["x"].as_i64()? as isize;
+    let y = location["y"].as_i64()? as isize;
+
+    let mut cells: Vec<(isize, isize)> = offsets(name)?
+        .iter()
+        .map(|&(dx, dy)| match orientation {
+            0 => (dx, dy),
+            1 => (dy, -dx),
+            2 => (-dx, -dy),
+            _ => (-dy, dx),
+        })
+        .map(|(dx, dy)| (rows as isize - 1 - (y + dy), x + dx))
+        .collect();
+    cells.sort();
+    Some((name.to_string(), cells))
+}
+
+fn location(board: &Board, cells: &[(isize, isize)], name: &str) -> Option<Value> {
+    //The other way around, trying every center and orientation until the cells line up
+    let rows = board.rows() as isize;
+    for orientation in ORIENTATIONS.iter() {
+        for (y, x) in cells.iter() {
+            for (dx, dy) in offsets(name)?.iter() {
+                let guess = json!({
+                    "type": name,
+                    "orientation": orientation,
+                    "x": x - dx,
+                    "y": rows - 1 - y - dy,
+                });
+                if location_cells(board.rows(), &guess).is_some_and(|(_, c)| c == cells) {
+                    return Some(guess);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn board_json(board: &Board) -> Value {
+    //Rows from the bottom up, each cell the piece it came from, G for garbage or null
+    let rows: Vec<Value> = (0..board.rows())
+        .rev()
+        .map(|y| {
+            (0..board.rules.width)
+                .map(|x| match board.field.get(y, x) {
+                    Square::FULL(shape) => json!(board.pieces.name(shape)),
+                    Square::GARBAGE => json!("G"),
+                    Square::EMPTY => Value::Null,
+                })
+                .collect()
+        })
+        .collect();
+    Value::Array(rows)
+}
+
+fn start_json(board: &Board) -> Value {
+    let mut queue = vec![board.pieces.name(board.player.shape)];
+    queue.extend(board.grab_bag.iter().rev().map(|s| board.pieces.name(*s)));
+
+    json!({
+        "type": "start",
+        "hold": board.held.map(|s| board.pieces.name(s)),
+        "queue": queue,
+        "combo": 0,
+        "back_to_back": false,
+        "board": board_json(board),
+    })
+}
+
+pub fn supported(rules: &Rules, pieces: &PieceSet) -> bool {
+    rules.width == 10
+        && rules.height + rules.buffer == 40
+        && pieces.name == "standard"
+}
+
+//Where a suggestion wants the piece to go
+struct Target {
+    shape: Shape,
+    cells: Vec<(isize, isize)>,
+    play: Value,
+}
+
+//A bot in another process, playing through the same actions as the keyboard
+pub struct External {
+    child: Child,
+    stdin: ChildStdin,
+    replies: Receiver<Value>,
+    name: String,
+    started: bool,
+    dealt: usize,
+    garbage: usize,
+    planned_for: Option<usize>,
+    waiting: bool,
+    target: Option<Target>,
+    played: Option<Value>,
+    dropped: bool,
+}
+
+impl External {
+    pub fn launch(command: &str) -> Result<External, String> {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        if words.is_empty() {
+            return Err("no bot to run".to_string());
+        }
+
+        let mut child = Command::new(words[0])
+            .args(&words[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("{}: {}", words[0], e))?;
+
+        //Replies are read on their own thread so the game never waits on the bot
+        let stdout = child.stdout.take().unwrap();
+        let (sender, replies) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let message = match line.ok().and_then(|l| serde_json::from_str(&l).ok()) {
+                    Some(message) => message,
+                    None => break,
+                };
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stdin = child.stdin.take().unwrap();
+        let mut external = External {
+            child,
+            stdin,
+            replies,
+            name: words[0].to_string(),
+            started: false,
+            dealt: 0,
+            garbage: 0,
+            planned_for: None,
+            waiting: false,
+            target: None,
+            played: None,
+            dropped: false,
+        };
+
+        //The bot introduces itself, hears the rules and says when it's ready
+        let info = external.wait("info")?;
+        if info["type"] != "info" {
+            return Err(format!("expected info from the bot, got {}", info));
+        }
+        if let Some(name) = info["name"].as_str() {
+            external.name = name.to_string();
+        }
+        external.send(json!({"type": "rules"}));
+        let ready = external.wait("ready")?;
+        if ready["type"] != "ready" {
+            return Err(format!("{} isn't ready: {}", external.name, ready));
+        }
+        Ok(external)
+    }
+
+    fn wait(&self, expected: &str) -> Result<Value, String> {
+        self.replies.recv_timeout(STARTUP).map_err(|e| match e {
+            RecvTimeoutError::Timeout => format!("{} never sent {}", self.name, expected),
+            RecvTimeoutError::Disconnected => {
+                format!("{} stopped talking before sending {}", self.name, expected)
+            }
+        })
+    }
+
+    fn send(&mut self, message: Value) {
+        //A bot that went away just stops getting messages, the piece keeps falling anyway
+        let _ = writeln!(self.stdin, "{}", message);
+        let _ = self.stdin.flush();
+    }
+
+    fn sync(&mut self, game: &Game) {
+        //Tells the bot everything that happened since it last heard from us
+        let board = &game.board;
+
+        if !self.started || game.garbage_rows != self.garbage {
+            //Garbage isn't part of the protocol, so the bot starts over with the new board
+            if self.started {
+                self.send(json!({"type": "stop"}));
+            }
+            self.send(start_json(board));
+            self.started = true;
+            self.dealt = board.dealt;
+            self.garbage = game.garbage_rows;
+            self.planned_for = None;
+            self.played = None;
+        }
+
+        if self.planned_for != Some(board.locked) {
+            if let Some(play) = self.played.take() {
+                self.send(json!({"type": "play", "move": play}));
+            }
+        }
+
+        //New pieces come in at the front of the grab bag, oldest last
+        let new = board.dealt - self.dealt;
+        for shape in board.grab_bag[..new].iter().rev() {
+            self.send(json!({"type": "new_piece", "piece": board.pieces.name(*shape)}));
+        }
+        self.dealt = board.dealt;
+
+        if self.planned_for != Some(board.locked) {
+            self.send(json!({"type": "suggest"}));
+            self.planned_for = Some(board.locked);
+            self.waiting = true;
+            self.target = None;
+            self.dropped = false;
+        }
+    }
+
+    fn choose(&self, game: &Game, suggestion: &Value) -> Option<Target> {
+        //The first move that's the current piece, or the one holding would bring in
+        let board = &game.board;
+        let other = board.held.or_else(|| board.grab_bag.last().copied());
+
+        for play in suggestion["moves"].as_array()?.iter() {
+            let (name, cells) = match location_cells(board.rows(), &play["location"]) {
+                Some(found) => found,
+                None => continue,
+            };
+            let shape = board.pieces.find(&name)?;
+            let holding = shape != board.player.shape;
+            if holding && (!game.can_hold || !board.rules.hold || Some(shape) != other) {
+                continue;
+            }
+            return Some(Target {
+                shape,
+                cells,
+                play: play.clone(),
+            });
+        }
+        None
+    }
+
+    pub fn action(&mut self, game: &Game) -> Option<Action> {
+        self.sync(game);
+
+        if self.waiting {
+            match self.replies.try_recv() {
+                Ok(reply) if reply["type"] == "suggestion" => {
+                    self.waiting = false;
+                    self.target = self.choose(game, &reply);
+                }
+                Ok(_) | Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.waiting = false;
+                    return None;
+                }
+            }
+        }
+
+        let board = &game.board;
+        let target = self.target.as_ref()?;
+        if target.shape != board.player.shape {
+            return Some(Action::Hold);
+        }
+
+        match crate::search::path_to(board, &board.player, &target.cells) {
+            Some(path) => {
+                self.played = Some(target.play.clone());
+                ai::follow(board, &path, &mut self.dropped)
+            }
+            None => {
+                //Somewhere we can't get to, so the bot has to start over after this piece
+                self.target = None;
+                self.started = false;
+                None
+            }
+        }
+    }
+}
+
+impl Drop for External {
+    fn drop(&mut self) {
+        self.send(json!({"type": "quit"}));
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+//The bot side, for trying the protocol out without anything else installed
+struct Stub {
+    board: Board,
+    queue: VecDeque<Shape>,
+    weights: Weights,
+}
+
+impl Stub {
+    fn start(&mut self, message: &Value) -> Option<()> {
+        let board = &mut self.board;
+        let rows = message["board"].as_array()?;
+        board.field = Playfield::new(board.rules.width, board.rows());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, cell) in row.as_array()?.iter().enumerate() {
+                let square = match cell.as_str() {
+                    None => continue,
+                    Some("G") => Square::GARBAGE,
+                    Some(name) => Square::FULL(board.pieces.find(name)?),
+                };
+                let row = (rows.len() - 1 - y) as isize;
+                board.field.place(row, x as isize, &[1], square);
+            }
+        }
+
+        board.held = match message["hold"].as_str() {
+            Some(name) => Some(board.pieces.find(name)?),
+            None => None,
+        };
+        self.queue = message["queue"]
+            .as_array()?
+            .iter()
+            .map(|name| board.pieces.find(name.as_str()?))
+            .collect::<Option<_>>()?;
+        Some(())
+    }
+
+    fn suggest(&mut self) -> Option<Value> {
+        //Same choice the built-in bot makes, between the current piece and the other one
+        let (board, queue) = (&mut self.board, &self.queue);
+        let current = *queue.front()?;
+        board.player = board.spawn(current);
+        board.grab_bag = queue.iter().skip(1).rev().copied().collect();
+
+        let mut options = vec![current];
+        if let Some(other) = board.held.or_else(|| queue.get(1).copied()) {
+            options.push(other);
+        }
+
+        let mut best: Option<(f64, Player)> = None;
+        for shape in options {
+            let start = board.spawn(shape);
+            if let Some((score, target)) = ai::best(board, &start, &self.weights) {
+                if best.is_none_or(|(top, _)| score > top) {
+                    best = Some((score, target));
+                }
+            }
+        }
+
+        let moves: Vec<Value> = best
+            .and_then(|(_, target)| {
+                location(board, &board.cells(&target), board.pieces.name(target.shape))
+            })
+            .map(|location| json!({"location": location, "spin": "none"}))
+            .into_iter()
+            .collect();
+        Some(json!({"type": "suggestion", "moves": moves}))
+    }
+
+    fn play(&mut self, message: &Value) -> Option<()> {
+        let board = &mut self.board;
+        let (name, cells) = location_cells(board.rows(), &message["move"]["location"])?;
+        let shape = board.pieces.find(&name)?;
+
+        //Playing something other than the current piece means it went through hold
+        let current = self.queue.pop_front()?;
+        if shape != current && board.held.replace(current).is_none() {
+            self.queue.pop_front()?;
+        }
+
+        for (y, x) in cells {
+            board.field.place(y, x, &[1], Square::FULL(shape));
+        }
+        board.field.clear_lines();
+        Some(())
+    }
+}
+
+pub fn stub(args: Vec<String>) {
+    let mut weights = Weights::standard();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--weights" {
+            weights = args
+                .next()
+                .and_then(|w| Weights::load(&w))
+                .expect("--weights takes a file or a list like holes=-0.4");
+        }
+    }
+
+    let pieces = Arc::new(PieceSet::builtin("standard"));
+    let mut stub = Stub {
+        board: Board::new(Rules::modern(), pieces, 0, 0),
+        queue: VecDeque::new(),
+        weights,
+    };
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut say = |message: Value| {
+        let _ = writeln!(out, "{}", message);
+        let _ = out.flush();
+    };
+
+    say(json!({
+        "type": "info",
+        "name": "ncurses_tetris",
+        "version": env!("CARGO_PKG_VERSION"),
+        "author": env!("CARGO_PKG_AUTHORS"),
+        "features": [],
+    }));
+
+    for line in io::stdin().lock().lines() {
+        let message: Value = match line.ok().and_then(|l| serde_json::from_str(&l).ok()) {
+            Some(message) => message,
+            None => break,
+        };
+
+        match message["type"].as_str() {
+            Some("rules") => say(json!({"type": "ready"})),
+            Some("start") if stub.start(&message).is_none() => {
+                say(json!({"type": "error", "reason": "unsupported_rules"}));
+            }
+            Some("suggest") => {
+                let suggestion = stub.suggest();
+                say(suggestion.unwrap_or_else(|| json!({"type": "suggestion", "moves": []})));
+            }
+            Some("play") => {
+                stub.play(&message);
+            }
+            Some("new_piece") => {
+                if let Some(shape) = message["piece"]
+                    .as_str()
+                    .and_then(|n| stub.board.pieces.find(n))
+                {
+                    stub.queue.push_back(shape);
+                }
+            }
+            Some("quit") => break,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::ai::Weights;
+use crate::sim::simulate;
+use crate::{parse_options, Options};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::{fs, thread};
+
+fn normalized(array: [f64; 5]) -> [f64; 5] {
+    //Only the direction matters to the bot, so every candidate is kept the same length
+    let length = array.iter().map(|w| w * w).sum::<f64>().sqrt();
+    if length == 0.0 {
+        array
+    } else {
+        array.map(|w| w / length)
+    }
+}
+
+fn random(rng: &mut StdRng) -> Weights {
+    let mut array = [0.0; 5];
+    for w in array.iter_mut() {
+        *w = rng.gen_range(-1.0, 1.0);
+    }
+    Weights::from_array(normalized(array))
+}
+
+fn child(rng: &mut StdRng, a: (f64, Weights), b: (f64, Weights)) -> Weights {
+    //Parents count for as much as they scored, then one weight might get nudged
+    let (a_score, b_score) = if a.0 + b.0 > 0.0 { (a.0, b.0) } else { (1.0, 1.0) };
+    let (a, b) = (a.1.to_array(), b.1.to_array());
+
+    let mut array = [0.0; 5];
+    for (i, w) in array.iter_mut().enumerate() {
+        *w = (a[i] * a_score + b[i] * b_score) / (a_score + b_score);
+    }
+    if rng.gen_range(0, 10) < 3 {
+        array[rng.gen_range(0, 5)] += rng.gen_range(-0.2, 0.2);
+    }
+    Weights::from_array(normalized(array))
+}
+
+fn fitness(
+    options: &Options,
+    candidates: &[Weights],
+    seeds: &[u64],
+    limit: usize,
+    threads: usize,
+) -> Vec<f64> {
+    //Everyone plays the same games, split evenly over the threads
+    let mut scores = vec![0.0; candidates.len()];
+    let chunk = candidates.len().div_ceil(threads);
+
+    thread::scope(|scope| {
+        for (weights, scores) in candidates.chunks(chunk).zip(scores.chunks_mut(chunk)) {
+            scope.spawn(move || {
+                for (w, score) in weights.iter().zip(scores.iter_mut()) {
+                    let lines: usize = seeds
+                        .iter()
+                        .map(|seed| simulate(options, w, *seed, limit).lines)
+                        .sum();
+                    *score = lines as f64 / seeds.len() as f64;
+                }
+            });
+        }
+    });
+    scores
+}
+
+pub fn run(args: Vec<String>) {
+    //Flags for the tuner itself, everything else sets up the games like normal
+    let mut generations = 20;
+    let mut population = 16;
+    let mut games = 4;
+    let mut limit = 500;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut out = "weights.txt".to_string();
+    let mut rest = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| -> usize {
+            args.next()
+                .and_then(|n| n.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or_else(|| panic!("{} takes a number", name))
+        };
+        match arg.as_str() {
+            "--generations" => generations = number("--generations"),
+            "--population" => population = std::cmp::max(number("--population"), 2),
+            "--games" => games = number("--games"),
+            "--limit" => limit = number("--limit"),
+            "--threads" => threads = number("--threads"),
+            "--out" => out = args.next().expect("--out takes a file"),
+            _ => rest.push(arg),
+        }
+    }
+
+    let options = parse_options(rest);
+    let mut rng = StdRng::seed_from_u64(options.seed.unwrap_or(0));
+
+    //Whatever the bot plays with now is one of the starting candidates
+    let mut candidates = vec![Weights::from_array(normalized(options.weights.to_array()))];
+    while candidates.len() < population {
+        candidates.push(random(&mut rng));
+    }
+
+    //Each generation plays different games, so its best is also played on these to be compared
+    //with the best so far
+    let benchmark: Vec<u64> = (0..games).map(|_| rng.gen_range(0, 1_000_000)).collect();
+    let mut champion: Option<f64> = None;
+
+    for generation in 1..=generations {
+        let seeds: Vec<u64> = (0..games).map(|_| rng.gen_range(0, 1_000_000)).collect();
+        let scores = fitness(&options, &candidates, &seeds, limit, threads);
+
+        let mut ranked: Vec<(f64, Weights)> = scores.into_iter().zip(candidates).collect();
+        ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let average = ranked.iter().map(|(score, _)| score).sum::<f64>() / ranked.len() as f64;
+        let (best_score, best) = ranked[0];
+        eprintln!(
+            "generation {}: best {:.1} lines, average {:.1} ({})",
+            generation,
+            best_score,
+            average,
+            best.to_text()
+        );
+
+        //Saved as soon as it's better so stopping early still leaves something to play with
+        let benchmarked = fitness(&options, &[best], &benchmark, limit, threads)[0];
+        if champion.is_none_or(|score| benchmarked > score) {
+            champion = Some(benchmarked);
+            eprintln!("  {:.1} lines on the benchmark games, the best so far", benchmarked);
+            let text = format!(
+                "//generation {}, {:.1} lines on average over the benchmark games\n{}\n",
+                generation,
+                benchmarked,
+                best.to_text()
+            );
+            if let Err(e) = fs::write(&out, text) {
+                eprintln!("Could not save weights to {}: {}", out, e);
+                std::process::exit(1);
+            }
+        } else {
+            eprintln!("  {:.1} lines on the benchmark games", benchmarked);
+        }
+
+        //The better half carries on and has children to fill in the rest
+        ranked.truncate(population.div_ceil(2));
+        candidates = ranked.iter().map(|(_, w)| *w).collect();
+        while candidates.len() < population {
+            let a = *ranked.choose(&mut rng).unwrap();
+            let b = *ranked.choose(&mut rng).unwrap();
+            candidates.push(child(&mut rng, a, b));
+        }
+    }
+
+    eprintln!("Best weights are in {}, play them with --ai --weights {}", out, out);
+}
@@ -0,0 +1,73 @@
+use crate::ai::Bot;
+use crate::game::Game;
+use crate::{Action, Controller, Options, Windows};
+use ncurses::*;
+use rand::{thread_rng, Rng};
+use std::{thread, time};
+
+//How a versus game went, the winner is None if it was quit early
+pub struct Outcome {
+    pub games: Vec<Game>,
+    pub winner: Option<usize>,
+}
+
+pub fn play(options: &Options) -> Outcome {
+    //Both sides get the same pieces so only the playing decides it
+    let seed = options
+        .seed
+        .unwrap_or_else(|| thread_rng().gen_range(0, 1_000_000));
+
+    //Against the bot the player gets the whole keyboard
+    let mut controllers = match &options.opponent {
+        Some(difficulty) => [
+            Controller::Keys(Action::from_key),
+            Controller::Bot(Bot::difficulty(options.weights, difficulty).unwrap()),
+        ],
+        None => [
+            Controller::Keys(Action::from_left_key),
+            Controller::Keys(Action::from_right_key),
+        ],
+    };
+    let names = match options.opponent {
+        Some(_) => ["YOU", "BOT"],
+        None => ["PLAYER 1", "PLAYER 2"],
+    };
+    let windows = [
+        Windows::at(&options.rules, &options.pieces, COLS() / 4, names[0]),
+        Windows::at(&options.rules, &options.pieces, COLS() * 3 / 4, names[1]),
+    ];
+    let mut games = vec![Game::new(options, seed), Game::new(options, seed)];
+
+    let ten_millis = time::Duration::from_millis(10);
+
+    let winner = 'game: loop {
+        let key = getch();
+        if key == 27 {
+            break None;
+        }
+
+        for i in 0..2 {
+            let action = controllers[i].action(key, &games[i]);
+            if games[i].tick(action).is_some() {
+                break 'game Some(1 - i);
+            }
+
+            //Whatever wasn't used up canceling garbage goes to the other side
+            let sent = games[i].attack.take_outgoing();
+            games[1 - i].attack.receive(sent);
+        }
+
+        for (game, windows) in games.iter_mut().zip(windows.iter()) {
+            game.board.draw(windows);
+            game.board.draw_next(windows);
+            game.board.draw_held(windows);
+            game.board.draw_score(windows);
+            game.finesse.draw(windows.message);
+            game.attack.draw(windows.meter);
+        }
+
+        thread::sleep(ten_millis);
+    };
+
+    Outcome { games, winner }
+}